@@ -0,0 +1,108 @@
+//! Compares `LSystem::render()` with rendering a state by looking up each token's name in the
+//! arena, which is how `render()` used to work, and times stepping deterministic, stochastic
+//! and context-sensitive systems.
+//!
+//! Run with `cargo bench --bench render`.
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use dcc_lsystem::{ArenaId, LSystemBuilder, LSystemError};
+
+const RUNS: u32 = 20;
+
+/// Returns the average time taken by `f` over `RUNS` runs.
+fn time<T, F: FnMut() -> T>(mut f: F) -> Duration {
+    let start = Instant::now();
+
+    for _ in 0..RUNS {
+        black_box(f());
+    }
+
+    start.elapsed() / RUNS
+}
+
+fn bench(label: &str, names: [&str; 2], depth: usize) -> Result<(), LSystemError> {
+    let mut builder = LSystemBuilder::new();
+    let a = builder.token(names[0])?;
+    let b = builder.token(names[1])?;
+    builder.axiom(vec![a])?;
+    builder.transformation_rule(a, vec![a, b])?;
+    builder.transformation_rule(b, vec![a])?;
+
+    let arena = builder.arena.clone();
+    let mut system = builder.finish()?;
+    system.step_by(depth);
+
+    let lookup = |state: &[ArenaId]| {
+        let mut rendered = String::new();
+        for &id in state {
+            rendered.push_str(arena.get(id).unwrap().name());
+        }
+        rendered
+    };
+
+    assert_eq!(lookup(system.get_state()), system.render());
+
+    let old = time(|| lookup(system.get_state()));
+    let new = time(|| system.render());
+
+    println!(
+        "{} (depth {}, {} tokens): arena lookup {:?}, render {:?}",
+        label,
+        depth,
+        system.get_state().len(),
+        old,
+        new
+    );
+
+    Ok(())
+}
+
+fn bench_step(label: &str, builder: LSystemBuilder, depth: usize) -> Result<(), LSystemError> {
+    let mut system = builder.finish()?;
+
+    let elapsed = time(|| {
+        system.reset();
+        system.step_by(depth);
+        system.get_state().len()
+    });
+
+    println!(
+        "{} (depth {}, {} tokens): step {:?}",
+        label,
+        depth,
+        system.get_state().len(),
+        elapsed
+    );
+
+    Ok(())
+}
+
+fn main() -> Result<(), LSystemError> {
+    bench("algae", ["A", "B"], 25)?;
+    bench("algae with long names", ["Apex", "Bud"], 25)?;
+
+    let algae = || -> Result<_, LSystemError> {
+        let mut builder = LSystemBuilder::new();
+        let a = builder.token("A")?;
+        let b = builder.token("B")?;
+        builder.axiom(vec![a])?;
+        builder.transformation_rule(a, vec![a, b])?;
+        Ok((builder, a, b))
+    };
+
+    let (mut builder, a, b) = algae()?;
+    builder.transformation_rule(b, vec![a])?;
+    bench_step("deterministic algae", builder, 25)?;
+
+    let (mut builder, a, b) = algae()?;
+    builder.stochastic_rule(b, vec![(1.0, vec![a]), (1.0, vec![a, b])])?;
+    bench_step("stochastic algae", builder, 22)?;
+
+    let (mut builder, a, b) = algae()?;
+    builder.transformation_rule(b, vec![a])?;
+    builder.context_rule(vec![a], b, vec![a], vec![b, a])?;
+    bench_step("context-sensitive algae", builder, 22)?;
+
+    Ok(())
+}
@@ -0,0 +1,247 @@
+//! Analytical tools for predicting the growth of deterministic L-systems.
+//!
+//! In a D0L-system (a deterministic, context-free system rewritten in parallel) the number
+//! of times each token occurs in the next generation depends only on how often each token
+//! occurs in the current one.  The counts therefore evolve by a linear map, the
+//! [`ProductionMatrix`], which can be used to predict the size of far-off generations without
+//! deriving them, and to find the rate at which the system grows.
+//!
+//! # Example
+//! ```rust
+//! # use dcc_lsystem::LSystemError;
+//! # fn main() -> Result<(), LSystemError> {
+//! use dcc_lsystem::analysis::ProductionMatrix;
+//! use dcc_lsystem::LSystemBuilder;
+//!
+//! let mut builder = LSystemBuilder::new();
+//! let a = builder.token("A")?;
+//! let b = builder.token("B")?;
+//! builder.axiom(vec![a])?;
+//! builder.transformation_rule(a, vec![a, b])?;
+//! builder.transformation_rule(b, vec![a])?;
+//! let system = builder.finish()?;
+//!
+//! let matrix = ProductionMatrix::new(&system).unwrap();
+//!
+//! // The lengths of Lindenmayer's algae are the Fibonacci numbers...
+//! assert_eq!(matrix.predicted_length(10), 144);
+//! assert_eq!(matrix.predicted_length(100), 927372692193078999176);
+//!
+//! // ...which grow by the golden ratio
+//! assert!((matrix.growth_rate() - 1.618034).abs() < 1e-6);
+//! # Ok(())
+//! # }
+//! ```
+use crate::arena::ArenaId;
+use crate::system::LSystem;
+
+// Power iteration stops once successive estimates of the growth rate agree this closely
+const TOLERANCE: f64 = 1e-12;
+const MAX_ITERATIONS: usize = 10_000;
+
+/// The production matrix of a D0L-system.  The entry in row `i` and column `j` is the number
+/// of times token `j` occurs in the successor of token `i`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ProductionMatrix {
+    rows: Vec<Vec<u128>>,
+
+    // The number of times each token occurs in the axiom
+    axiom: Vec<u128>,
+}
+
+impl ProductionMatrix {
+    /// Build the production matrix of `system`, or return `None` if `system` isn't a
+    /// D0L-system.  That is, it must be rewritten in
+    /// [`RewriteMode::Parallel`](crate::system::RewriteMode::Parallel), and every token must
+    /// have a single context-free successor.
+    pub fn new(system: &LSystem) -> Option<Self> {
+        let successors = system.deterministic_successors()?;
+
+        let rows = successors
+            .iter()
+            .map(|successor| counts(successor, successors.len()))
+            .collect();
+
+        Some(Self {
+            rows,
+            axiom: counts(system.get_axiom(), successors.len()),
+        })
+    }
+
+    /// Returns the number of tokens in the alphabet of the system, which is the number of
+    /// rows (and columns) of the matrix.
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// Returns `true` if the system has no tokens.
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Returns the number of times `to` occurs in the successor of `from`.
+    ///
+    /// # Panics
+    /// Panics if either id doesn't belong to the system.
+    pub fn get(&self, from: ArenaId, to: ArenaId) -> u128 {
+        self.rows[from.0][to.0]
+    }
+
+    /// Returns the number of times each token (indexed by [`ArenaId`]) occurs in the `n`-th
+    /// generation of the system, saturating at `u128::MAX`.
+    ///
+    /// This takes time logarithmic in `n`, so it is cheap even for enormous `n`.
+    pub fn predicted_counts(&self, n: usize) -> Vec<u128> {
+        let mut counts = self.axiom.clone();
+        let mut power = self.rows.clone();
+        let mut n = n;
+
+        while n > 0 {
+            if n & 1 == 1 {
+                counts = apply(&counts, &power);
+            }
+
+            n >>= 1;
+            if n > 0 {
+                power = multiply(&power, &power);
+            }
+        }
+
+        counts
+    }
+
+    /// Returns the number of tokens in the `n`-th generation of the system, saturating at
+    /// `u128::MAX`.  See [`ProductionMatrix::predicted_counts()`].
+    pub fn predicted_length(&self, n: usize) -> u128 {
+        self.predicted_counts(n)
+            .into_iter()
+            .fold(0, u128::saturating_add)
+    }
+
+    /// Returns the asymptotic growth rate of the system: the dominant eigenvalue of the
+    /// production matrix.  A system with a growth rate above 1 grows exponentially, with each
+    /// generation roughly `growth_rate()` times longer than the last.  A growth rate of 1
+    /// means the system grows at most polynomially, and 0 that every token eventually dies
+    /// out.
+    ///
+    /// The eigenvalue is estimated by power iteration, so it is only accurate to a few parts
+    /// per trillion, and converges slowly for systems with polynomial growth.
+    pub fn growth_rate(&self) -> f64 {
+        if self.rows.is_empty() {
+            return 0.0;
+        }
+
+        // Iterating with M + I rather than M shifts every eigenvalue up by one, which leaves
+        // the dominant eigenvalue strictly largest in magnitude even for periodic systems.
+        let mut vector = vec![1.0 / self.rows.len() as f64; self.rows.len()];
+        let mut estimate = 0.0;
+
+        for _ in 0..MAX_ITERATIONS {
+            let mut next = vector.clone();
+
+            for (row, &weight) in self.rows.iter().zip(vector.iter()) {
+                for (entry, &count) in next.iter_mut().zip(row.iter()) {
+                    *entry += weight * count as f64;
+                }
+            }
+
+            // `vector` sums to one, so the sum of `next` is the growth of this iteration
+            let total: f64 = next.iter().sum();
+            next.iter_mut().for_each(|entry| *entry /= total);
+            vector = next;
+
+            let previous = std::mem::replace(&mut estimate, total - 1.0);
+            if (estimate - previous).abs() <= TOLERANCE * estimate.max(1.0) {
+                break;
+            }
+        }
+
+        estimate.max(0.0)
+    }
+}
+
+/// Returns the number of times each of `len` tokens occurs in `tokens`.
+fn counts(tokens: &[ArenaId], len: usize) -> Vec<u128> {
+    let mut counts = vec![0; len];
+
+    for id in tokens.iter() {
+        counts[id.0] += 1;
+    }
+
+    counts
+}
+
+/// Returns the row vector `vector` multiplied by `matrix`, saturating on overflow.
+fn apply(vector: &[u128], matrix: &[Vec<u128>]) -> Vec<u128> {
+    let mut result = vec![0u128; vector.len()];
+
+    for (row, &weight) in matrix.iter().zip(vector.iter()) {
+        if weight == 0 {
+            continue;
+        }
+
+        for (entry, &count) in result.iter_mut().zip(row.iter()) {
+            *entry = entry.saturating_add(weight.saturating_mul(count));
+        }
+    }
+
+    result
+}
+
+/// Returns the product of two square matrices, saturating on overflow.
+fn multiply(left: &[Vec<u128>], right: &[Vec<u128>]) -> Vec<Vec<u128>> {
+    left.iter().map(|row| apply(row, right)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{LSystemBuilder, LSystemError};
+
+    #[test]
+    fn test_production_matrix() -> Result<(), LSystemError> {
+        let mut builder = LSystemBuilder::new();
+        let f = builder.token("F")?;
+        let plus = builder.token("+")?;
+        let x = builder.token("X")?;
+        builder.axiom(vec![x, plus])?;
+        builder.transformation_rule(f, vec![f, f])?;
+        builder.transformation_rule(x, vec![f, plus, x, f])?;
+
+        let mut system = builder.clone().finish()?;
+        let matrix = ProductionMatrix::new(&system).unwrap();
+        assert_eq!(matrix.get(x, f), 2);
+        assert_eq!(matrix.get(x, x), 1);
+        assert_eq!(matrix.get(plus, plus), 1);
+
+        for n in 0..12 {
+            assert_eq!(matrix.predicted_length(n), system.get_state().len() as u128);
+            system.step();
+        }
+
+        assert!((matrix.growth_rate() - 2.0).abs() < 1e-9);
+        assert_eq!(matrix.predicted_length(1000), u128::MAX);
+
+        // Systems that aren't D0L-systems have no production matrix
+        builder.stochastic_rule(plus, vec![(1.0, vec![plus]), (1.0, vec![])])?;
+        assert_eq!(ProductionMatrix::new(&builder.finish()?), None);
+
+        // Systems with polynomial growth, and systems that die out
+        let mut builder = LSystemBuilder::new();
+        let a = builder.token("A")?;
+        let b = builder.token("B")?;
+        builder.axiom(vec![a])?;
+        builder.transformation_rule(a, vec![a, b])?;
+        let matrix = ProductionMatrix::new(&builder.clone().finish()?).unwrap();
+        assert!((matrix.growth_rate() - 1.0).abs() < 1e-3);
+
+        let mut builder = LSystemBuilder::new();
+        let a = builder.token("A")?;
+        builder.axiom(vec![a])?;
+        builder.transformation_rule(a, vec![])?;
+        let matrix = ProductionMatrix::new(&builder.finish()?).unwrap();
+        assert_eq!(matrix.growth_rate(), 0.0);
+
+        Ok(())
+    }
+}
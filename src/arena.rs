@@ -154,7 +154,7 @@ impl<T> Arena<T> {
     /// assert_eq!(iterator.next(), Some(&9));
     /// assert_eq!(iterator.next(), Some(&16));
     /// ```
-    pub fn iter_mut(&mut self) -> IterMut<T> {
+    pub fn iter_mut(&mut self) -> IterMut<'_, T> {
         self.arena.iter_mut()
     }
 
@@ -237,6 +237,81 @@ impl<T> Arena<T> {
         }
     }
 
+    /// Remove the element with the given id from this arena, returning it if the id was
+    /// valid.
+    ///
+    /// The arena is kept compact, so every element after the removed one moves down to the
+    /// previous id.  Use [`Arena::retain()`] to also get a [`Remapping`] from the old ids to
+    /// the new ones.
+    ///
+    /// # Example
+    /// ```rust
+    /// use dcc_lsystem::{Arena, ArenaId};
+    ///
+    /// let mut arena = Arena::new();
+    /// let x = arena.push("x");
+    /// let y = arena.push("y");
+    /// let z = arena.push("z");
+    ///
+    /// assert_eq!(arena.remove(y), Some("y"));
+    /// assert_eq!(arena.remove(ArenaId(7)), None);
+    ///
+    /// // `z` has moved into the slot `y` was in
+    /// assert_eq!(arena.as_slice(), &["x", "z"]);
+    /// assert_eq!(arena.get(y), Some(&"z"));
+    /// ```
+    pub fn remove(&mut self, id: ArenaId) -> Option<T> {
+        if self.is_valid(id) {
+            Some(self.arena.remove(id.0))
+        } else {
+            None
+        }
+    }
+
+    /// Keep only the elements for which `keep` returns `true`, compacting the arena.  Returns
+    /// a [`Remapping`] which gives the new id of each element that was kept.
+    ///
+    /// # Example
+    /// ```rust
+    /// use dcc_lsystem::Arena;
+    ///
+    /// let mut arena = Arena::new();
+    /// let x = arena.push(1);
+    /// let y = arena.push(2);
+    /// let z = arena.push(3);
+    ///
+    /// // Remove the even numbers
+    /// let remapping = arena.retain(|_, value| value % 2 == 1);
+    ///
+    /// assert_eq!(arena.as_slice(), &[1, 3]);
+    /// assert_eq!(remapping.get(y), None);
+    /// assert_eq!(arena.get(remapping.get(z).unwrap()), Some(&3));
+    /// ```
+    pub fn retain<F>(&mut self, mut keep: F) -> Remapping
+    where
+        F: FnMut(ArenaId, &T) -> bool,
+    {
+        let mut ids = Vec::with_capacity(self.arena.len());
+        let mut kept = 0;
+
+        for (index, value) in self.arena.iter().enumerate() {
+            if keep(ArenaId(index), value) {
+                ids.push(Some(ArenaId(kept)));
+                kept += 1;
+            } else {
+                ids.push(None);
+            }
+        }
+
+        let mut index = 0;
+        self.arena.retain(|_| {
+            index += 1;
+            ids[index - 1].is_some()
+        });
+
+        Remapping { ids }
+    }
+
     /// Returns a slice representing the memory underlying this arena.
     ///
     /// # Example
@@ -255,6 +330,43 @@ impl<T> Arena<T> {
     }
 }
 
+/// Maps the ids of an [`Arena`] from before some of its elements were removed to their ids
+/// afterwards.  See [`Arena::retain()`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Remapping {
+    // The new id of each old id, or `None` if the element was removed
+    ids: Vec<Option<ArenaId>>,
+}
+
+impl Remapping {
+    /// Returns the new id of the element that had id `id`, or `None` if it was removed (or
+    /// was never part of the arena).
+    pub fn get(&self, id: ArenaId) -> Option<ArenaId> {
+        self.ids.get(id.0).copied().flatten()
+    }
+
+    /// Returns the new ids of each of `ids`, leaving out any that were removed.
+    ///
+    /// # Example
+    /// ```rust
+    /// use dcc_lsystem::Arena;
+    ///
+    /// let mut arena = Arena::new();
+    /// let x = arena.push('x');
+    /// let y = arena.push('y');
+    /// let z = arena.push('z');
+    ///
+    /// let remapping = arena.retain(|id, _| id != x);
+    /// let ids = remapping.apply(&[x, y, z, x]);
+    ///
+    /// let values: Vec<_> = ids.into_iter().map(|id| *arena.get(id).unwrap()).collect();
+    /// assert_eq!(values, vec!['y', 'z']);
+    /// ```
+    pub fn apply(&self, ids: &[ArenaId]) -> Vec<ArenaId> {
+        ids.iter().filter_map(|&id| self.get(id)).collect()
+    }
+}
+
 /// An iterator that yields the current ArenaId and the element during iterator.
 ///
 /// # Examples
@@ -410,4 +522,24 @@ mod tests {
         assert_eq!(enumerator.next(), Some((c, &4)));
         assert_eq!(enumerator.next(), Some((d, &8)));
     }
+
+    #[test]
+    fn arena_retain() {
+        let mut arena = Arena::new();
+
+        let a = arena.push('a');
+        let b = arena.push('b');
+        let c = arena.push('c');
+        let d = arena.push('d');
+
+        let remapping = arena.retain(|id, _| id == b || id == d);
+
+        assert_eq!(arena.as_slice(), &['b', 'd']);
+        assert_eq!(remapping.get(a), None);
+        assert_eq!(remapping.get(b), Some(ArenaId(0)));
+        assert_eq!(remapping.get(c), None);
+        assert_eq!(remapping.get(d), Some(ArenaId(1)));
+        assert_eq!(remapping.get(ArenaId(4)), None);
+        assert_eq!(remapping.apply(&[d, c, b]), vec![ArenaId(1), ArenaId(0)]);
+    }
 }
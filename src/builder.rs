@@ -1,23 +1,154 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
-use crate::arena::{Arena, ArenaId};
+use crate::arena::{Arena, ArenaId, Remapping};
 use crate::errors::LSystemError;
-use crate::system::LSystem;
+use crate::pool::BufferPool;
+use crate::rng::Rng;
+use crate::successors::SuccessorTable;
+use crate::system::{AxiomSource, LSystem, RewriteMode};
 use crate::token::Token;
 
 #[derive(Debug, Clone)]
 pub struct TransformationRule {
     predecessor: ArenaId,
     successor: Vec<ArenaId>,
+    weight: f64,
+
+    // Whether this rule is kept alongside the earlier rules for its predecessor (as another
+    // alternative of a stochastic rule, or an accumulated duplicate), rather than being
+    // subject to the duplicate rule policy when the builder is finished
+    alternative: bool,
 }
 
 impl TransformationRule {
     pub fn new(predecessor: ArenaId, successor: Vec<ArenaId>) -> Self {
+        Self::weighted(predecessor, successor, 1.0)
+    }
+
+    /// Create a rule that is chosen with the given relative weight whenever its predecessor
+    /// has more than one rule.
+    pub fn weighted(predecessor: ArenaId, successor: Vec<ArenaId>, weight: f64) -> Self {
         Self {
             predecessor,
             successor,
+            weight,
+            alternative: false,
+        }
+    }
+
+    /// Returns a rule with the given parts that belongs to the same (possibly stochastic) rule
+    /// as this one does.
+    pub(crate) fn remade(
+        &self,
+        predecessor: ArenaId,
+        successor: Vec<ArenaId>,
+        weight: f64,
+    ) -> Self {
+        Self {
+            alternative: self.alternative,
+            ..Self::weighted(predecessor, successor, weight)
         }
     }
+
+    /// Returns the token rewritten by this rule.
+    pub fn predecessor(&self) -> ArenaId {
+        self.predecessor
+    }
+
+    /// Returns the tokens the predecessor is rewritten to.
+    pub fn successor(&self) -> &[ArenaId] {
+        &self.successor
+    }
+
+    /// Returns the relative weight of this rule among the rules for its predecessor.
+    pub fn weight(&self) -> f64 {
+        self.weight
+    }
+}
+
+/// A context-sensitive transformation rule `left < predecessor > right => successor`, which only
+/// rewrites `predecessor` when it is immediately preceded by the tokens `left` and followed by
+/// the tokens `right`.  An empty context matches anything.
+#[derive(Debug, Clone)]
+pub struct ContextRule {
+    left: Vec<ArenaId>,
+    predecessor: ArenaId,
+    right: Vec<ArenaId>,
+    successor: Vec<ArenaId>,
+}
+
+impl ContextRule {
+    pub fn new(
+        left: Vec<ArenaId>,
+        predecessor: ArenaId,
+        right: Vec<ArenaId>,
+        successor: Vec<ArenaId>,
+    ) -> Self {
+        Self {
+            left,
+            predecessor,
+            right,
+            successor,
+        }
+    }
+
+    /// Returns the tokens that must immediately precede the predecessor.
+    pub fn left(&self) -> &[ArenaId] {
+        &self.left
+    }
+
+    /// Returns the token rewritten by this rule.
+    pub fn predecessor(&self) -> ArenaId {
+        self.predecessor
+    }
+
+    /// Returns the tokens that must immediately follow the predecessor.
+    pub fn right(&self) -> &[ArenaId] {
+        &self.right
+    }
+
+    /// Returns the tokens the predecessor is rewritten to.
+    pub fn successor(&self) -> &[ArenaId] {
+        &self.successor
+    }
+}
+
+/// What an [`LSystemBuilder`] does when more than one transformation rule is given for the
+/// same token.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum DuplicateRulePolicy {
+    /// Adding a second rule for a token is an error.
+    Error,
+    /// The most recently added rule for a token replaces any earlier ones.
+    #[default]
+    Replace,
+    /// Every rule for a token is kept as an equally likely alternative.  Each time the token
+    /// is rewritten, one of its rules is chosen at random (using the seed of the system).
+    Accumulate,
+}
+
+/// The findings of [`LSystemBuilder::validate()`].  None of these stop a system from being
+/// built, but in a hand-written grammar they often point to a typo.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct ValidationReport {
+    /// Tokens that never appear in any generation of the system, nor in the interpretation of
+    /// a token that does.
+    pub unreachable: Vec<ArenaId>,
+
+    /// Tokens without any production rule, which are never rewritten.
+    pub terminals: Vec<ArenaId>,
+
+    /// Tokens that have production rules even though they are unreachable, so that their
+    /// rules are never applied.
+    pub unused_rules: Vec<ArenaId>,
+}
+
+impl ValidationReport {
+    /// Returns `true` if every token is reachable.  Terminals are expected in most systems,
+    /// so they don't count against the report.
+    pub fn is_ok(&self) -> bool {
+        self.unreachable.is_empty()
+    }
 }
 
 /// A struct for constructing [`LSystem`]s.
@@ -54,7 +185,22 @@ impl TransformationRule {
 pub struct LSystemBuilder {
     pub arena: Arena<Token>,
     pub axiom: Option<Vec<ArenaId>>,
+
+    // Rules pushed here directly are subject to the duplicate rule policy when the builder is
+    // finished, just like the rules added with `transformation_rule`
     pub rules: Vec<TransformationRule>,
+    pub context_rules: Vec<ContextRule>,
+    context_ignore: Vec<ArenaId>,
+    mode: RewriteMode,
+    seed: u64,
+    threads: usize,
+    memoize: bool,
+    pool: Option<BufferPool>,
+    duplicates: DuplicateRulePolicy,
+    axiom_source: Option<AxiomSource>,
+    interpretations: Vec<(ArenaId, Vec<ArenaId>)>,
+    tables: Vec<(String, Vec<TransformationRule>)>,
+    schedule: BTreeMap<usize, Option<String>>,
 }
 
 impl LSystemBuilder {
@@ -83,6 +229,135 @@ impl LSystemBuilder {
         Ok(self.arena.push(Token::new(name)?))
     }
 
+    /// Returns the [`ArenaId`] of the most recently registered token with the given name,
+    /// or `None` if no such token exists.
+    ///
+    /// ```rust
+    /// # use dcc_lsystem::LSystemError;
+    /// # fn main() -> Result<(), LSystemError> {
+    /// use dcc_lsystem::LSystemBuilder;
+    ///
+    /// let mut builder = LSystemBuilder::new();
+    /// let a = builder.token("a")?;
+    ///
+    /// assert_eq!(builder.get_token("a"), Some(a));
+    /// assert_eq!(builder.get_token("b"), None);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn get_token(&self, name: &str) -> Option<ArenaId> {
+        self.arena
+            .enumerate()
+            .filter(|(_, token)| token.name() == name)
+            .map(|(id, _)| id)
+            .last()
+    }
+
+    /// Remove a token from this builder, along with its transformation rules.  Where the
+    /// token appears in the successors of other rules or in the axiom it is erased, and
+    /// context-sensitive rules whose contexts use the token are removed, since they can no
+    /// longer match.
+    ///
+    /// Removing a token changes the ids of every token registered after it, so this function
+    /// returns a [`Remapping`] from the old ids to the new ones.  Rules and axioms held by the
+    /// builder are updated automatically, but an [axiom
+    /// generator](LSystemBuilder::axiom_generator()) must be replaced by the caller.  If the
+    /// axiom is left with no tokens it is cleared.
+    ///
+    /// This function will return an [`LSystemError::InvalidArenaId`] error if `id` is invalid.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::LSystemError;
+    /// # fn main() -> Result<(), LSystemError> {
+    /// use dcc_lsystem::LSystemBuilder;
+    ///
+    /// let mut builder = LSystemBuilder::new();
+    /// let a = builder.token("a")?;
+    /// let b = builder.token("b")?;
+    /// let c = builder.token("c")?;
+    /// builder.axiom(vec![a, b, c])?;
+    /// builder.transformation_rule(a, vec![a, b])?;
+    /// builder.transformation_rule(b, vec![c, c])?;
+    ///
+    /// let remapping = builder.remove_token(b)?;
+    /// assert_eq!(remapping.get(b), None);
+    /// assert_eq!(builder.get_token("c"), remapping.get(c));
+    ///
+    /// let mut system = builder.finish()?;
+    /// system.step();
+    /// assert_eq!(system.render(), "ac");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn remove_token(&mut self, id: ArenaId) -> Result<Remapping, LSystemError> {
+        self.validate_ids(&[id])?;
+
+        let remapping = self.arena.retain(|other, _| other != id);
+
+        self.rules.retain(|rule| rule.predecessor != id);
+        for rule in self.rules.iter_mut() {
+            // unwrap: only rules for `id` were removed
+            rule.predecessor = remapping.get(rule.predecessor).unwrap();
+            rule.successor = remapping.apply(&rule.successor);
+        }
+
+        self.context_rules.retain(|rule| {
+            rule.predecessor != id && !rule.left.contains(&id) && !rule.right.contains(&id)
+        });
+        for rule in self.context_rules.iter_mut() {
+            // unwrap: only rules mentioning `id` were removed
+            rule.predecessor = remapping.get(rule.predecessor).unwrap();
+            rule.left = remapping.apply(&rule.left);
+            rule.right = remapping.apply(&rule.right);
+            rule.successor = remapping.apply(&rule.successor);
+        }
+
+        for (_, rules) in self.tables.iter_mut() {
+            rules.retain(|rule| rule.predecessor != id);
+            for rule in rules.iter_mut() {
+                // unwrap: only rules for `id` were removed
+                rule.predecessor = remapping.get(rule.predecessor).unwrap();
+                rule.successor = remapping.apply(&rule.successor);
+            }
+        }
+
+        self.context_ignore.retain(|&other| other != id);
+        self.context_ignore = remapping.apply(&self.context_ignore);
+
+        self.interpretations.retain(|(other, _)| *other != id);
+        for (other, tokens) in self.interpretations.iter_mut() {
+            // unwrap: the interpretation of `id` was removed
+            *other = remapping.get(*other).unwrap();
+            *tokens = remapping.apply(tokens);
+        }
+
+        self.axiom = self
+            .axiom
+            .take()
+            .map(|axiom| remapping.apply(&axiom))
+            .filter(|axiom| !axiom.is_empty());
+
+        self.axiom_source = match self.axiom_source.take() {
+            Some(AxiomSource::Weighted(alternatives)) => {
+                let alternatives: Vec<_> = alternatives
+                    .into_iter()
+                    .map(|(weight, axiom)| (weight, remapping.apply(&axiom)))
+                    .filter(|(_, axiom)| !axiom.is_empty())
+                    .collect();
+
+                if alternatives.is_empty() {
+                    None
+                } else {
+                    Some(AxiomSource::Weighted(alternatives))
+                }
+            }
+            source => source,
+        };
+
+        Ok(remapping)
+    }
+
     fn validate_ids(&self, ids: &[ArenaId]) -> Result<(), LSystemError> {
         for &id in ids {
             if !self.arena.is_valid(id) {
@@ -93,9 +368,39 @@ impl LSystemBuilder {
         Ok(())
     }
 
+    /// Applies the [`DuplicateRulePolicy`] of this builder before a new rule for
+    /// `predecessor` is added, returning whether the new rule is to be kept alongside the
+    /// existing rules for `predecessor`.  Returns an [`LSystemError::DuplicateRule`] error if
+    /// `predecessor` already has a rule and duplicate rules are not allowed.
+    fn apply_duplicate_policy(&mut self, predecessor: ArenaId) -> Result<bool, LSystemError> {
+        let duplicate = self
+            .rules
+            .iter()
+            .any(|rule| rule.predecessor == predecessor);
+
+        if duplicate {
+            match self.duplicates {
+                DuplicateRulePolicy::Error => {
+                    // unwrap: the predecessor of every rule is validated before it is added
+                    let name = self.arena.get(predecessor).unwrap().name();
+                    return Err(LSystemError::DuplicateRule(name.to_string()));
+                }
+                DuplicateRulePolicy::Replace => {
+                    self.rules.retain(|rule| rule.predecessor != predecessor);
+                    return Ok(false);
+                }
+                DuplicateRulePolicy::Accumulate => {}
+            }
+        }
+
+        Ok(duplicate)
+    }
+
     /// Register a new transformation rule in this LSystem.
     ///
-    /// This function will return an error if any of the provided TokenId are invalid.
+    /// This function will return an error if any of the provided TokenId are invalid, or if
+    /// `predecessor` already has a rule and the [`DuplicateRulePolicy`] is
+    /// [`DuplicateRulePolicy::Error`].
     ///
     /// ```rust
     /// # use dcc_lsystem::LSystemError;
@@ -119,16 +424,324 @@ impl LSystemBuilder {
         // Verify that all provided TokenId's correspond to a token in this LSystem.
         self.validate_ids(&[predecessor])?;
         self.validate_ids(&successor)?;
+        let alternative = self.apply_duplicate_policy(predecessor)?;
 
         // Add the rule to this system
-        self.rules
-            .push(TransformationRule::new(predecessor, successor));
+        self.rules.push(TransformationRule {
+            alternative,
+            ..TransformationRule::new(predecessor, successor)
+        });
+
+        Ok(())
+    }
+
+    /// Register a new stochastic transformation rule in this LSystem.  Each time `predecessor`
+    /// is rewritten, one of the given successors is chosen at random, in proportion to its
+    /// weight.  The choices are made using the [seed](LSystemBuilder::seed()) of the system.
+    ///
+    /// A stochastic rule counts as a single rule for the purposes of the
+    /// [`DuplicateRulePolicy`].
+    ///
+    /// This function will return an [`LSystemError::InvalidRule`] error if no successors are
+    /// given, an [`LSystemError::InvalidWeight`] error if any of the weights aren't positive and
+    /// finite, and otherwise fails in the same way as
+    /// [`LSystemBuilder::transformation_rule()`].
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::LSystemError;
+    /// # fn main() -> Result<(), LSystemError> {
+    /// use dcc_lsystem::LSystemBuilder;
+    ///
+    /// let mut builder = LSystemBuilder::new();
+    /// let a = builder.token("a")?;
+    /// let b = builder.token("b")?;
+    /// builder.axiom(vec![a])?;
+    ///
+    /// // `a` becomes `ab` 30% of the time, and stays as `a` otherwise
+    /// builder.stochastic_rule(a, vec![(0.3, vec![a, b]), (0.7, vec![a])])?;
+    /// builder.seed(42);
+    ///
+    /// let mut system = builder.finish()?;
+    /// system.step_by(20);
+    ///
+    /// let len = system.get_state().len();
+    /// assert!(len > 1 && len < 21);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn stochastic_rule(
+        &mut self,
+        predecessor: ArenaId,
+        successors: Vec<(f64, Vec<ArenaId>)>,
+    ) -> Result<(), LSystemError> {
+        self.validate_ids(&[predecessor])?;
+
+        if successors.is_empty() {
+            // unwrap: we just validated `predecessor`
+            let name = self.arena.get(predecessor).unwrap().name();
+            return Err(LSystemError::InvalidRule(format!(
+                "{} has no successors",
+                name
+            )));
+        }
+
+        for (weight, successor) in successors.iter() {
+            if !(weight.is_finite() && *weight > 0.0) {
+                return Err(LSystemError::InvalidWeight(*weight));
+            }
+
+            self.validate_ids(successor)?;
+        }
+
+        let accumulated = self.apply_duplicate_policy(predecessor)?;
+
+        for (index, (weight, successor)) in successors.into_iter().enumerate() {
+            self.rules.push(TransformationRule {
+                alternative: accumulated || index > 0,
+                ..TransformationRule::weighted(predecessor, successor, weight)
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Register a new context-sensitive transformation rule `left < predecessor > right =>
+    /// successor` in this LSystem.  The rule only rewrites `predecessor` when it is immediately
+    /// preceded by `left` and followed by `right`; either context may be empty, in which case it
+    /// matches anything.
+    ///
+    /// Context-sensitive rules take priority over the ordinary rules for a token, and are tried
+    /// in the order they were added.  Where no context-sensitive rule matches, the ordinary rules
+    /// apply.  Contexts are always matched against the state before the step, so rewriting one
+    /// token never affects the context seen by its neighbours.  Tokens can be hidden from
+    /// contexts with [`LSystemBuilder::context_ignore()`].  The [`DuplicateRulePolicy`]
+    /// doesn't apply to context-sensitive rules.
+    ///
+    /// This function will return an error if any of the provided TokenId are invalid.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::LSystemError;
+    /// # fn main() -> Result<(), LSystemError> {
+    /// use dcc_lsystem::LSystemBuilder;
+    ///
+    /// // A signal `b` travelling to the right through a row of `a`s
+    /// let mut builder = LSystemBuilder::new();
+    /// let a = builder.token("a")?;
+    /// let b = builder.token("b")?;
+    /// builder.axiom(vec![b, a, a, a])?;
+    /// builder.context_rule(vec![b], a, vec![], vec![b])?;
+    /// builder.transformation_rule(b, vec![a])?;
+    ///
+    /// let mut system = builder.finish()?;
+    /// system.step();
+    /// assert_eq!(system.render(), "abaa");
+    /// system.step();
+    /// assert_eq!(system.render(), "aaba");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn context_rule(
+        &mut self,
+        left: Vec<ArenaId>,
+        predecessor: ArenaId,
+        right: Vec<ArenaId>,
+        successor: Vec<ArenaId>,
+    ) -> Result<(), LSystemError> {
+        self.validate_ids(&left)?;
+        self.validate_ids(&[predecessor])?;
+        self.validate_ids(&right)?;
+        self.validate_ids(&successor)?;
+
+        self.context_rules
+            .push(ContextRule::new(left, predecessor, right, successor));
+
+        Ok(())
+    }
+
+    /// Skip over the given tokens when matching the contexts of context-sensitive rules, as
+    /// with the `#ignore` directive of *The Algorithmic Beauty of Plants*.  This is typically
+    /// used to hide the geometric tokens of a bracketed system (such as `+`, `-`, `[` and `]`)
+    /// so that contexts can be matched across them.
+    ///
+    /// Ignored tokens are only skipped in the state, so a context that itself contains an
+    /// ignored token never matches.  Calling this function again adds to the tokens already
+    /// ignored.
+    ///
+    /// This function will return an [`LSystemError::InvalidArenaId`] error if any of the ids
+    /// are invalid.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::LSystemError;
+    /// # fn main() -> Result<(), LSystemError> {
+    /// use dcc_lsystem::LSystemBuilder;
+    ///
+    /// // A signal `b` travelling to the right through `a`s, ignoring the `+`s in between
+    /// let mut builder = LSystemBuilder::new();
+    /// let a = builder.token("a")?;
+    /// let b = builder.token("b")?;
+    /// let plus = builder.token("+")?;
+    /// builder.axiom(vec![b, plus, a, plus, plus, a])?;
+    /// builder.context_rule(vec![b], a, vec![], vec![b])?;
+    /// builder.transformation_rule(b, vec![a])?;
+    /// builder.context_ignore(vec![plus])?;
+    ///
+    /// let mut system = builder.finish()?;
+    /// system.step();
+    /// assert_eq!(system.render(), "a+b++a");
+    /// system.step();
+    /// assert_eq!(system.render(), "a+a++b");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn context_ignore(&mut self, tokens: Vec<ArenaId>) -> Result<(), LSystemError> {
+        self.validate_ids(&tokens)?;
+
+        for id in tokens {
+            if !self.context_ignore.contains(&id) {
+                self.context_ignore.push(id);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Define a rule table called `name`, replacing any previous table with that name.  A
+    /// system can switch between its rule tables as it grows (see [`LSystem::set_table()`] and
+    /// [`LSystemBuilder::table_schedule()`]), which lets the production rules change over time,
+    /// for example between the vegetative and flowering phases of a plant.
+    ///
+    /// While a table is in use, its rules replace the default rules of every token it has
+    /// rules for; other tokens keep their default rules.  Several rules for the same token make
+    /// up a stochastic rule, chosen between in proportion to their weights as with
+    /// [`LSystemBuilder::stochastic_rule()`].  Context-sensitive rules apply whichever table
+    /// is in use.
+    ///
+    /// This function will return an [`LSystemError::InvalidArenaId`] error if any of the ids
+    /// are invalid, or an [`LSystemError::InvalidWeight`] error if any of the weights aren't
+    /// positive and finite.
+    pub fn rule_table<S: Into<String>>(
+        &mut self,
+        name: S,
+        rules: Vec<TransformationRule>,
+    ) -> Result<(), LSystemError> {
+        for rule in rules.iter() {
+            self.validate_ids(&[rule.predecessor])?;
+            self.validate_ids(&rule.successor)?;
+
+            if !(rule.weight.is_finite() && rule.weight > 0.0) {
+                return Err(LSystemError::InvalidWeight(rule.weight));
+            }
+        }
+
+        let name = name.into();
+        self.tables.retain(|(table, _)| *table != name);
+        self.tables.push((name, rules));
+
+        Ok(())
+    }
+
+    /// Use the rule table called `name` (or the default rules, for `None`) to rewrite every
+    /// generation from `generation` onwards, until the next scheduled change.
+    ///
+    /// This function will return an [`LSystemError::UnknownTable`] error if no table called
+    /// `name` has been defined.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::LSystemError;
+    /// # fn main() -> Result<(), LSystemError> {
+    /// use dcc_lsystem::builder::TransformationRule;
+    /// use dcc_lsystem::LSystemBuilder;
+    ///
+    /// let mut builder = LSystemBuilder::new();
+    /// let a = builder.token("a")?;
+    /// let b = builder.token("b")?;
+    /// builder.axiom(vec![a])?;
+    /// builder.transformation_rule(a, vec![a, b])?;
+    /// builder.rule_table("doubling", vec![TransformationRule::new(b, vec![b, b])])?;
+    ///
+    /// // Double up the `b`s for generations 2 and 3 only
+    /// builder.table_schedule(2, Some("doubling"))?;
+    /// builder.table_schedule(4, None)?;
+    ///
+    /// let mut system = builder.finish()?;
+    /// system.step_by(5);
+    /// assert_eq!(system.render(), "abbbbbbbbbbbb");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn table_schedule(
+        &mut self,
+        generation: usize,
+        name: Option<&str>,
+    ) -> Result<(), LSystemError> {
+        if let Some(name) = name {
+            if !self.tables.iter().any(|(table, _)| table == name) {
+                return Err(LSystemError::UnknownTable(name.to_string()));
+            }
+        }
+
+        self.schedule.insert(generation, name.map(str::to_string));
+
+        Ok(())
+    }
+
+    /// Set the interpretation of `id` to `tokens`.  Interpretations are a final rewriting step
+    /// which is only applied when the state is interpreted (see [`LSystem::interpreted()`]),
+    /// and never takes part in the derivation.  This lets bookkeeping tokens be drawn as some
+    /// other sequence of tokens, or hidden entirely by giving them an empty interpretation.
+    ///
+    /// Setting the interpretation of a token again replaces its previous interpretation.
+    ///
+    /// This function will return an [`LSystemError::InvalidArenaId`] error if any of the ids
+    /// are invalid.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::LSystemError;
+    /// # fn main() -> Result<(), LSystemError> {
+    /// use dcc_lsystem::LSystemBuilder;
+    ///
+    /// let mut builder = LSystemBuilder::new();
+    /// let a = builder.token("A")?;
+    /// let f = builder.token("F")?;
+    /// let plus = builder.token("+")?;
+    /// builder.axiom(vec![a])?;
+    /// builder.transformation_rule(a, vec![a, plus, a])?;
+    ///
+    /// // `A` is drawn as `F+F`
+    /// builder.interpretation(a, vec![f, plus, f])?;
+    ///
+    /// let mut system = builder.finish()?;
+    /// system.step();
+    /// assert_eq!(system.render(), "A+A");
+    /// assert_eq!(system.render_interpreted(), "F+F+F+F");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn interpretation(
+        &mut self,
+        id: ArenaId,
+        tokens: Vec<ArenaId>,
+    ) -> Result<(), LSystemError> {
+        self.validate_ids(&[id])?;
+        self.validate_ids(&tokens)?;
+
+        self.interpretations.retain(|(other, _)| *other != id);
+        self.interpretations.push((id, tokens));
 
         Ok(())
     }
 
     /// Set the axiom for this LSystem.
     ///
+    /// This function will return an [`LSystemError::EmptyAxiom`] error if the axiom contains
+    /// no tokens, or an [`LSystemError::InvalidArenaId`] error if any of the provided ids are
+    /// invalid.
+    ///
     /// # Example
     /// ```rust
     /// # use dcc_lsystem::LSystemError;
@@ -146,14 +759,364 @@ impl LSystemBuilder {
     /// # }
     /// ```
     pub fn axiom(&mut self, axiom: Vec<ArenaId>) -> Result<(), LSystemError> {
+        if axiom.is_empty() {
+            return Err(LSystemError::EmptyAxiom);
+        }
+
         self.validate_ids(axiom.as_slice())?;
         self.axiom = Some(axiom);
+        self.axiom_source = None;
+
+        Ok(())
+    }
+
+    /// Set a stochastic axiom for this LSystem.  Each axiom is given along with a weight, and
+    /// the resulting [`LSystem`] chooses one of them at random (in proportion to the weights)
+    /// when it is built, and again each time it is [reset](LSystem::reset()).  The choices
+    /// are made using the [seed](LSystemBuilder::seed()) of the system.
+    ///
+    /// This function will return an [`LSystemError::EmptyAxiom`] error if no axioms are given
+    /// or any of them are empty, an [`LSystemError::InvalidWeight`] error if any of the weights
+    /// aren't positive and finite, or an [`LSystemError::InvalidArenaId`] error if any of the
+    /// provided ids are invalid.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::LSystemError;
+    /// # fn main() -> Result<(), LSystemError> {
+    /// use dcc_lsystem::LSystemBuilder;
+    ///
+    /// let mut builder = LSystemBuilder::new();
+    /// let a = builder.token("a")?;
+    /// let b = builder.token("b")?;
+    /// builder.transformation_rule(a, vec![a, b])?;
+    ///
+    /// // Start from `a` three times out of four, and from `b` otherwise
+    /// builder.stochastic_axiom(vec![(3.0, vec![a]), (1.0, vec![b])])?;
+    ///
+    /// // Grow a batch of individuals from the same configuration
+    /// let mut starts = Vec::new();
+    /// for seed in 0..20 {
+    ///     let mut individual = builder.clone();
+    ///     individual.seed(seed);
+    ///     starts.push(individual.finish()?.get_axiom().to_vec());
+    /// }
+    ///
+    /// assert!(starts.contains(&vec![a]));
+    /// assert!(starts.contains(&vec![b]));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn stochastic_axiom(
+        &mut self,
+        alternatives: Vec<(f64, Vec<ArenaId>)>,
+    ) -> Result<(), LSystemError> {
+        if alternatives.is_empty() {
+            return Err(LSystemError::EmptyAxiom);
+        }
+
+        for (weight, axiom) in alternatives.iter() {
+            if !(weight.is_finite() && *weight > 0.0) {
+                return Err(LSystemError::InvalidWeight(*weight));
+            }
+
+            if axiom.is_empty() {
+                return Err(LSystemError::EmptyAxiom);
+            }
+
+            self.validate_ids(axiom)?;
+        }
+
+        self.axiom = None;
+        self.axiom_source = Some(AxiomSource::Weighted(alternatives));
 
         Ok(())
     }
 
+    /// Use a closure to produce the axiom for this LSystem.  The closure is called with a
+    /// seeded [`Rng`] when the resulting [`LSystem`] is built, and again each time it is
+    /// [reset](LSystem::reset()).
+    ///
+    /// If the closure returns an empty axiom or one containing invalid ids,
+    /// [`LSystemBuilder::finish()`] and [`LSystem::try_reset()`] return an error, and
+    /// [`LSystem::reset()`] panics.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::LSystemError;
+    /// # fn main() -> Result<(), LSystemError> {
+    /// use dcc_lsystem::LSystemBuilder;
+    ///
+    /// let mut builder = LSystemBuilder::new();
+    /// let a = builder.token("a")?;
+    /// builder.transformation_rule(a, vec![a, a])?;
+    ///
+    /// // Start from between one and four `a`s
+    /// builder.axiom_generator(move |rng| vec![a; 1 + rng.gen_index(4)]);
+    ///
+    /// let mut system = builder.finish()?;
+    /// system.step_by(2);
+    ///
+    /// let len = system.get_axiom().len();
+    /// assert!((1..=4).contains(&len));
+    /// assert_eq!(system.get_state().len(), 4 * len);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn axiom_generator<F>(&mut self, generator: F)
+    where
+        F: Fn(&mut Rng) -> Vec<ArenaId> + Send + Sync + 'static,
+    {
+        self.axiom = None;
+        self.axiom_source = Some(AxiomSource::Generator(std::sync::Arc::new(generator)));
+    }
+
+    /// Set the [`RewriteMode`] used by the resulting [`LSystem`].  By default every token is
+    /// rewritten in parallel.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::LSystemError;
+    /// # fn main() -> Result<(), LSystemError> {
+    /// use dcc_lsystem::LSystemBuilder;
+    /// use dcc_lsystem::system::RewriteMode;
+    ///
+    /// let mut builder = LSystemBuilder::new();
+    /// let a = builder.token("a")?;
+    /// let b = builder.token("b")?;
+    /// builder.axiom(vec![a, a, a])?;
+    /// builder.transformation_rule(a, vec![b])?;
+    ///
+    /// // Only rewrite the leftmost `a` at each step
+    /// builder.rewrite_mode(RewriteMode::SequentialFirst);
+    ///
+    /// let mut system = builder.finish()?;
+    /// system.step();
+    /// assert_eq!(system.render(), "baa");
+    /// system.step();
+    /// assert_eq!(system.render(), "bba");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn rewrite_mode(&mut self, mode: RewriteMode) {
+        self.mode = mode;
+    }
+
+    /// Set the seed used by the resulting [`LSystem`] whenever it needs to make a random choice,
+    /// such as when using [`RewriteMode::SequentialRandom`].  Systems built with the same seed
+    /// make the same choices.
+    ///
+    /// Each generation draws from its own stream of random numbers derived from the seed, and
+    /// the choice of successor for each token of a generation from a stream of its own, so any
+    /// generation can be re-derived without replaying the ones before it.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::LSystemError;
+    /// # fn main() -> Result<(), LSystemError> {
+    /// use dcc_lsystem::LSystemBuilder;
+    /// use dcc_lsystem::system::RewriteMode;
+    ///
+    /// let mut builder = LSystemBuilder::new();
+    /// let a = builder.token("a")?;
+    /// let b = builder.token("b")?;
+    /// builder.axiom(vec![a, a, a, a])?;
+    /// builder.transformation_rule(a, vec![b])?;
+    /// builder.rewrite_mode(RewriteMode::SequentialRandom);
+    /// builder.seed(1234);
+    ///
+    /// let mut first = builder.clone().finish()?;
+    /// let mut second = builder.finish()?;
+    ///
+    /// first.step_by(2);
+    /// second.step_by(2);
+    /// assert_eq!(first.render(), second.render());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+
+    /// Set the number of threads the resulting [`LSystem`] uses to expand large states.
+    /// See [`LSystem::set_threads()`] for details.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::LSystemError;
+    /// # fn main() -> Result<(), LSystemError> {
+    /// use dcc_lsystem::LSystemBuilder;
+    ///
+    /// let mut builder = LSystemBuilder::new();
+    /// let a = builder.token("a")?;
+    /// builder.axiom(vec![a])?;
+    /// builder.threads(8);
+    ///
+    /// let system = builder.finish()?;
+    /// assert_eq!(system.threads(), 8);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn threads(&mut self, threads: usize) {
+        self.threads = threads;
+    }
+
+    /// Turn on memoized expansion in the resulting [`LSystem`], so that deep derivations of
+    /// deterministic systems are taken in one go.  See [`LSystem::set_memoize()`] for details.
+    pub fn memoize(&mut self, memoize: bool) {
+        self.memoize = memoize;
+    }
+
+    /// Make the resulting [`LSystem`] allocate its state buffers from (and return them to)
+    /// the given [`BufferPool`].  See the [`pool`](crate::pool) module for details.
+    pub fn buffer_pool(&mut self, pool: BufferPool) {
+        self.pool = Some(pool);
+    }
+
+    /// Set what happens when more than one transformation rule is given for the same token.
+    /// By default the most recently added rule replaces any earlier ones.
+    ///
+    /// The policy is applied as rules are added, so it only affects rules added after it is
+    /// set.  Every rule left in [`LSystemBuilder::rules`] when the builder is finished is used as
+    /// an alternative for its predecessor.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::LSystemError;
+    /// # fn main() -> Result<(), LSystemError> {
+    /// use dcc_lsystem::LSystemBuilder;
+    /// use dcc_lsystem::builder::DuplicateRulePolicy;
+    ///
+    /// let mut builder = LSystemBuilder::new();
+    /// let a = builder.token("a")?;
+    /// let b = builder.token("b")?;
+    /// builder.axiom(vec![a, a, a, a, a, a, a, a])?;
+    ///
+    /// // `a` becomes either `a` or `b`, each with probability one half
+    /// builder.duplicate_rule_policy(DuplicateRulePolicy::Accumulate);
+    /// builder.transformation_rule(a, vec![a])?;
+    /// builder.transformation_rule(a, vec![b])?;
+    ///
+    /// let mut system = builder.finish()?;
+    /// system.step_by(4);
+    /// assert!(system.render().contains('b'));
+    ///
+    /// // A second rule for the same token can also be rejected outright
+    /// let mut builder = LSystemBuilder::new();
+    /// let a = builder.token("a")?;
+    /// builder.duplicate_rule_policy(DuplicateRulePolicy::Error);
+    /// builder.transformation_rule(a, vec![a, a])?;
+    ///
+    /// assert!(matches!(
+    ///     builder.transformation_rule(a, vec![a]),
+    ///     Err(LSystemError::DuplicateRule(_))
+    /// ));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn duplicate_rule_policy(&mut self, policy: DuplicateRulePolicy) {
+        self.duplicates = policy;
+    }
+
+    /// Check the tokens and rules of this builder for mistakes, returning a
+    /// [`ValidationReport`] of tokens that can never appear, tokens that are never rewritten,
+    /// and rules that are never applied.
+    ///
+    /// A token is reachable if it appears in an axiom, in a successor of a rule (of any rule
+    /// table, or context-sensitive) for a reachable token, or in the interpretation of a
+    /// reachable token.  If the axiom is produced by an [axiom
+    /// generator](LSystemBuilder::axiom_generator()) then any token could appear in it, so
+    /// every token is taken to be reachable.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::LSystemError;
+    /// # fn main() -> Result<(), LSystemError> {
+    /// use dcc_lsystem::LSystemBuilder;
+    ///
+    /// let mut builder = LSystemBuilder::new();
+    /// let f = builder.token("F")?;
+    /// let plus = builder.token("+")?;
+    /// let g = builder.token("G")?;
+    /// builder.axiom(vec![f])?;
+    /// builder.transformation_rule(f, vec![f, plus, f])?;
+    ///
+    /// // A typo: `G` was meant to appear in the rule for `F`
+    /// builder.transformation_rule(g, vec![g, g])?;
+    ///
+    /// let report = builder.validate();
+    /// assert!(!report.is_ok());
+    /// assert_eq!(report.unreachable, vec![g]);
+    /// assert_eq!(report.terminals, vec![plus]);
+    /// assert_eq!(report.unused_rules, vec![g]);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn validate(&self) -> ValidationReport {
+        let len = self.arena.len();
+
+        // The tokens each token can be rewritten or interpreted to, indexed by ArenaId
+        let mut edges: Vec<Vec<ArenaId>> = vec![Vec::new(); len];
+        let mut has_rules = vec![false; len];
+
+        let rules = self
+            .rules
+            .iter()
+            .chain(self.tables.iter().flat_map(|(_, rules)| rules.iter()))
+            .map(|rule| (rule.predecessor, &rule.successor))
+            .chain(
+                self.context_rules
+                    .iter()
+                    .map(|rule| (rule.predecessor, &rule.successor)),
+            );
+
+        for (predecessor, successor) in rules {
+            has_rules[predecessor.0] = true;
+            edges[predecessor.0].extend_from_slice(successor);
+        }
+
+        for (id, tokens) in self.interpretations.iter() {
+            edges[id.0].extend_from_slice(tokens);
+        }
+
+        let mut stack: Vec<ArenaId> = match &self.axiom_source {
+            Some(AxiomSource::Generator(_)) => (0..len).map(ArenaId).collect(),
+            Some(AxiomSource::Weighted(alternatives)) => alternatives
+                .iter()
+                .flat_map(|(_, axiom)| axiom.iter().copied())
+                .collect(),
+            Some(AxiomSource::Fixed(axiom)) => axiom.clone(),
+            None => self.axiom.clone().unwrap_or_default(),
+        };
+
+        let mut reachable = vec![false; len];
+
+        while let Some(id) = stack.pop() {
+            if !std::mem::replace(&mut reachable[id.0], true) {
+                stack.extend_from_slice(&edges[id.0]);
+            }
+        }
+
+        ValidationReport {
+            unreachable: (0..len).filter(|&i| !reachable[i]).map(ArenaId).collect(),
+            terminals: (0..len).filter(|&i| !has_rules[i]).map(ArenaId).collect(),
+            unused_rules: (0..len)
+                .filter(|&i| !reachable[i] && has_rules[i])
+                .map(ArenaId)
+                .collect(),
+        }
+    }
+
     /// Consumes the builder, returning an LSystem instance.  If an axiom has not been
-    /// set then this function will return an [`LSystemError::MissingAxiom`] error.
+    /// set then this function will return an [`LSystemError::MissingAxiom`] error, and if
+    /// the weight of any rule isn't positive and finite it will return an
+    /// [`LSystemError::InvalidWeight`] error.
+    ///
+    /// The [`DuplicateRulePolicy`] also applies to rules pushed onto [`LSystemBuilder::rules`]
+    /// directly, each of which counts as a rule of its own.  With the default
+    /// [`DuplicateRulePolicy::Replace`] the last rule pushed for a token wins, and with
+    /// [`DuplicateRulePolicy::Error`] a second rule for a token is an
+    /// [`LSystemError::DuplicateRule`] error.
     ///
     /// # Example
     /// ```rust
@@ -174,26 +1137,122 @@ impl LSystemBuilder {
     /// # }
     /// ```
     pub fn finish(self) -> Result<LSystem, LSystemError> {
-        let axiom = self.axiom.ok_or(LSystemError::MissingAxiom)?;
+        let axioms = match self.axiom_source {
+            Some(source) => source,
+            None => AxiomSource::Fixed(self.axiom.ok_or(LSystemError::MissingAxiom)?),
+        };
 
-        // Construct a HashMap associating each variable with its corresponding transformation rule
-        let mut rules_map = HashMap::new();
+        // Rules pushed onto `rules` directly haven't had their weights checked
+        if let Some(rule) = self
+            .rules
+            .iter()
+            .find(|rule| !(rule.weight.is_finite() && rule.weight > 0.0))
+        {
+            return Err(LSystemError::InvalidWeight(rule.weight));
+        }
+
+        // Build a table indexed by ArenaId, associating each token with its weighted
+        // successors.
+        let mut rules_map: Vec<Vec<(f64, Vec<ArenaId>)>> = vec![Vec::new(); self.arena.len()];
 
         for rule in self.rules.into_iter() {
-            rules_map.insert(rule.predecessor, rule.successor);
+            let successors = &mut rules_map[rule.predecessor.0];
+
+            if !rule.alternative && !successors.is_empty() {
+                match self.duplicates {
+                    DuplicateRulePolicy::Error => {
+                        // unwrap: the rule was pushed for a token of this builder
+                        let name = self.arena.get(rule.predecessor).unwrap().name();
+                        return Err(LSystemError::DuplicateRule(name.to_string()));
+                    }
+                    DuplicateRulePolicy::Replace => successors.clear(),
+                    DuplicateRulePolicy::Accumulate => {}
+                }
+            }
+
+            successors.push((rule.weight, rule.successor));
+        }
+
+        // Tokens without a rule are constants, so they get the production rule P => P.
+        for (index, successors) in rules_map.iter_mut().enumerate() {
+            if successors.is_empty() {
+                successors.push((1.0, vec![ArenaId(index)]));
+            }
         }
 
-        // We also add constant production rules of the form P => P.
-        for (id, _token) in self.arena.enumerate() {
-            // no rule associated to this token, so its a constant token
-            rules_map.entry(id).or_insert_with(|| vec![id]);
+        let context_rules = self.context_rules;
+        let context_ignore = self.context_ignore;
+
+        // Each rule table replaces the default rules of the tokens it has rules for
+        let tables = self
+            .tables
+            .into_iter()
+            .map(|(name, rules)| {
+                let mut table_map = rules_map.clone();
+
+                for rule in rules.iter() {
+                    table_map[rule.predecessor.0].clear();
+                }
+
+                for rule in rules.into_iter() {
+                    table_map[rule.predecessor.0].push((rule.weight, rule.successor));
+                }
+
+                let mut table = SuccessorTable::new(table_map);
+                add_contexts(&mut table, &context_rules, &context_ignore);
+                (name, table)
+            })
+            .collect::<Vec<_>>();
+
+        let schedule = self
+            .schedule
+            .into_iter()
+            .map(|(generation, name)| {
+                // unwrap: scheduled tables are checked to exist when they are scheduled
+                let index =
+                    name.map(|name| tables.iter().position(|(table, _)| *table == name).unwrap());
+                (generation, index)
+            })
+            .collect();
+
+        let mut successors = SuccessorTable::new(rules_map);
+        add_contexts(&mut successors, &context_rules, &context_ignore);
+
+        let mut system = LSystem::new(
+            self.arena,
+            axioms,
+            successors,
+            self.mode,
+            self.seed,
+            self.threads,
+            self.pool,
+        )?;
+
+        for (id, tokens) in self.interpretations.into_iter() {
+            system.set_interpretation(id, tokens)?;
         }
 
-        // If we set our system up correctly, it should be that each token
-        // contributes exactly one rule, so we check for that here.
-        assert_eq!(self.arena.len(), rules_map.len());
+        system.set_tables(tables, schedule);
+        system.set_memoize(self.memoize);
 
-        Ok(LSystem::new(self.arena, axiom, rules_map))
+        Ok(system)
+    }
+}
+
+/// Adds the context-sensitive rules to `table`, skipping over the `ignored` tokens when
+/// matching contexts.
+fn add_contexts(table: &mut SuccessorTable, rules: &[ContextRule], ignored: &[ArenaId]) {
+    for &id in ignored {
+        table.ignore(id);
+    }
+
+    for rule in rules.iter() {
+        table.add_context(
+            rule.left.clone(),
+            rule.predecessor,
+            rule.right.clone(),
+            rule.successor.clone(),
+        );
     }
 }
 
@@ -223,12 +1282,53 @@ fn build_rules_string(rules: &[TransformationRule], arena: &Arena<Token>) -> Str
     st.join(",")
 }
 
+fn build_context_rules_string(rules: &[ContextRule], arena: &Arena<Token>) -> String {
+    let mut st = Vec::new();
+
+    for rule in rules {
+        st.push(format!(
+            "{} < {} > {} => {}",
+            render_tokens(arena.as_slice(), &rule.left),
+            render_tokens(arena.as_slice(), &[rule.predecessor]),
+            render_tokens(arena.as_slice(), &rule.right),
+            render_tokens(arena.as_slice(), &rule.successor),
+        ));
+    }
+
+    st.join(",")
+}
+
 impl std::fmt::Debug for LSystemBuilder {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
         f.debug_struct("LSystemBuilder")
             .field("arena", &self.arena)
             .field("axiom", &self.axiom)
             .field("rules", &build_rules_string(&self.rules, &self.arena))
+            .field(
+                "context_rules",
+                &build_context_rules_string(&self.context_rules, &self.arena),
+            )
+            .field(
+                "context_ignore",
+                &render_tokens(self.arena.as_slice(), &self.context_ignore),
+            )
+            .field("mode", &self.mode)
+            .field("seed", &self.seed)
+            .field("threads", &self.threads)
+            .field("memoize", &self.memoize)
+            .field("pool", &self.pool)
+            .field("duplicates", &self.duplicates)
+            .field("axiom_source", &self.axiom_source)
+            .field("interpretations", &self.interpretations)
+            .field(
+                "tables",
+                &self
+                    .tables
+                    .iter()
+                    .map(|(name, rules)| (name, build_rules_string(rules, &self.arena)))
+                    .collect::<Vec<_>>(),
+            )
+            .field("schedule", &self.schedule)
             .finish()
     }
 }
@@ -261,6 +1361,12 @@ mod tests {
         // `x` won't be valid for an empty builder
         assert!(some_other_builder.axiom(vec![x]).is_err());
 
+        // an axiom needs at least one token
+        assert!(matches!(
+            builder.axiom(vec![]),
+            Err(LSystemError::EmptyAxiom)
+        ));
+
         let q = some_other_builder.token("q")?;
 
         // make sure `y` still isn't valid
@@ -276,4 +1382,158 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_builder_stochastic_rule() -> Result<(), LSystemError> {
+        let mut builder = LSystemBuilder::new();
+        let a = builder.token("a")?;
+        let b = builder.token("b")?;
+
+        assert!(matches!(
+            builder.stochastic_rule(a, vec![]),
+            Err(LSystemError::InvalidRule(_))
+        ));
+
+        for weight in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(matches!(
+                builder.stochastic_rule(a, vec![(1.0, vec![a]), (weight, vec![b])]),
+                Err(LSystemError::InvalidWeight(_))
+            ));
+        }
+
+        // A stochastic rule replaces (and is replaced by) other rules as a whole
+        builder.transformation_rule(a, vec![b])?;
+        builder.stochastic_rule(a, vec![(1.0, vec![a]), (2.0, vec![a, b])])?;
+        assert_eq!(builder.rules.len(), 2);
+        assert_eq!(builder.rules[1].weight(), 2.0);
+
+        builder.transformation_rule(a, vec![b, b])?;
+        assert_eq!(builder.rules.len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_builder_remove_token() -> Result<(), LSystemError> {
+        let mut builder = LSystemBuilder::new();
+        let a = builder.token("a")?;
+        let b = builder.token("b")?;
+        let c = builder.token("c")?;
+        builder.stochastic_axiom(vec![(1.0, vec![b]), (2.0, vec![c, b, a])])?;
+        builder.transformation_rule(a, vec![a, b])?;
+        builder.context_rule(vec![b], c, vec![], vec![a])?;
+        builder.context_rule(vec![], c, vec![], vec![b, c, c])?;
+
+        let remapping = builder.remove_token(b)?;
+        let (a, c) = (remapping.get(a).unwrap(), remapping.get(c).unwrap());
+
+        assert_eq!(builder.arena.len(), 2);
+        assert_eq!(builder.rules[0].successor(), &[a]);
+        assert_eq!(builder.context_rules.len(), 1);
+        assert_eq!(builder.context_rules[0].predecessor(), c);
+        assert_eq!(builder.context_rules[0].successor(), &[c, c]);
+        assert!(
+            matches!(&builder.axiom_source, Some(AxiomSource::Weighted(axioms)) if axioms == &[(2.0, vec![c, a])])
+        );
+
+        assert!(matches!(
+            builder.remove_token(ArenaId(2)),
+            Err(LSystemError::InvalidArenaId(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_builder_validate() -> Result<(), LSystemError> {
+        let mut builder = LSystemBuilder::new();
+        let a = builder.token("a")?;
+        let b = builder.token("b")?;
+        let c = builder.token("c")?;
+        let d = builder.token("d")?;
+        let e = builder.token("e")?;
+        builder.axiom(vec![a])?;
+
+        // Tokens reached through a rule table, a context-sensitive rule and an interpretation
+        builder.rule_table("other", vec![TransformationRule::new(a, vec![b])])?;
+        builder.context_rule(vec![], b, vec![a], vec![c])?;
+        builder.interpretation(c, vec![d])?;
+        builder.transformation_rule(e, vec![a])?;
+
+        let report = builder.validate();
+        assert_eq!(report.unreachable, vec![e]);
+        assert_eq!(report.terminals, vec![c, d]);
+        assert_eq!(report.unused_rules, vec![e]);
+
+        // Any token could come out of an axiom generator
+        builder.axiom_generator(move |_| vec![a]);
+        assert!(builder.validate().is_ok());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_builder_rules_pushed_directly() -> Result<(), LSystemError> {
+        let mut builder = LSystemBuilder::new();
+        let a = builder.token("a")?;
+        let b = builder.token("b")?;
+        builder.axiom(vec![a; 64])?;
+
+        // With the default policy, the last rule pushed for a token wins
+        builder.rules.push(TransformationRule::new(a, vec![a]));
+        builder.rules.push(TransformationRule::new(a, vec![b]));
+        let mut system = builder.clone().finish()?;
+        system.step();
+        assert_eq!(system.token_counts()[&b], 64);
+
+        // Accumulating turns them into alternatives, as it does for added rules
+        let mut accumulated = builder.clone();
+        accumulated.duplicate_rule_policy(DuplicateRulePolicy::Accumulate);
+        let mut system = accumulated.finish()?;
+        system.step();
+        let counts = system.token_counts();
+        assert!(counts[&a] > 0 && counts[&b] > 0);
+
+        let mut rejected = builder.clone();
+        rejected.duplicate_rule_policy(DuplicateRulePolicy::Error);
+        assert!(matches!(
+            rejected.finish(),
+            Err(LSystemError::DuplicateRule(name)) if name == "a"
+        ));
+
+        // Their weights are still checked
+        let mut invalid = builder.clone();
+        invalid
+            .rules
+            .push(TransformationRule::weighted(b, vec![a], f64::NAN));
+        assert!(matches!(
+            invalid.finish(),
+            Err(LSystemError::InvalidWeight(weight)) if weight.is_nan()
+        ));
+
+        // The alternatives of a stochastic rule, and rules accumulated before the policy is
+        // changed, are kept
+        let mut stochastic = LSystemBuilder::new();
+        let a = stochastic.token("a")?;
+        let b = stochastic.token("b")?;
+        stochastic.axiom(vec![a; 64])?;
+        let mut accumulated = stochastic.clone();
+
+        stochastic.stochastic_rule(a, vec![(1.0, vec![a]), (1.0, vec![b])])?;
+        stochastic.duplicate_rule_policy(DuplicateRulePolicy::Error);
+
+        accumulated.duplicate_rule_policy(DuplicateRulePolicy::Accumulate);
+        accumulated.transformation_rule(a, vec![a])?;
+        accumulated.transformation_rule(a, vec![b])?;
+        accumulated.duplicate_rule_policy(DuplicateRulePolicy::Replace);
+
+        for builder in [stochastic, accumulated] {
+            let mut system = builder.finish()?;
+            system.step();
+            let counts = system.token_counts();
+            assert!(counts[&a] > 0 && counts[&b] > 0);
+        }
+
+        Ok(())
+    }
 }
@@ -0,0 +1,263 @@
+//! Derivation trees, which record how every token of a generation was produced.
+//!
+//! A [`Derivation`] holds every generation of a system from the axiom up to some depth, with
+//! each token linked to the token it was rewritten from (its parent) and the tokens it was
+//! rewritten to (its children).  Following the parents of a token of the last generation leads
+//! back through the rule applications that produced it to one of the tokens of the axiom.
+//!
+//! Since every intermediate generation is kept, a derivation takes far more memory than the
+//! generation itself, and is only built when asked for.
+//!
+//! # Example
+//! ```rust
+//! # use dcc_lsystem::LSystemError;
+//! # fn main() -> Result<(), LSystemError> {
+//! use dcc_lsystem::derivation::Derivation;
+//! use dcc_lsystem::LSystemBuilder;
+//!
+//! let mut builder = LSystemBuilder::new();
+//! let a = builder.token("A")?;
+//! let b = builder.token("B")?;
+//! builder.axiom(vec![a, b])?;
+//! builder.transformation_rule(a, vec![a, b])?;
+//! builder.transformation_rule(b, vec![a])?;
+//! let system = builder.finish()?;
+//!
+//! // A B => A B A => A B A A B
+//! let derivation = Derivation::new(&system, 2);
+//! let leaves: Vec<_> = derivation.generation(2).collect();
+//! assert_eq!(leaves.len(), 5);
+//!
+//! // The last token came from the `A` in the middle of generation 1, which came from the
+//! // `B` of the axiom
+//! let ancestors: Vec<_> = derivation
+//!     .ancestors(leaves[4])
+//!     .map(|index| derivation.get(index).unwrap().token())
+//!     .collect();
+//! assert_eq!(ancestors, vec![a, b]);
+//! assert_eq!(derivation.root(leaves[4]), 1);
+//! # Ok(())
+//! # }
+//! ```
+use std::ops::Range;
+
+use crate::arena::ArenaId;
+use crate::system::LSystem;
+
+/// A token of one of the generations of a [`Derivation`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DerivationNode {
+    token: ArenaId,
+    generation: usize,
+    parent: Option<usize>,
+    children: Range<usize>,
+    rewritten: bool,
+}
+
+impl DerivationNode {
+    /// Returns the token at this node.
+    pub fn token(&self) -> ArenaId {
+        self.token
+    }
+
+    /// Returns the generation this node belongs to.
+    pub fn generation(&self) -> usize {
+        self.generation
+    }
+
+    /// Returns the index of the node this node was rewritten from, or `None` for the tokens
+    /// of the axiom.
+    pub fn parent(&self) -> Option<usize> {
+        self.parent
+    }
+
+    /// Returns the indices of the nodes this node was rewritten to.  The range is empty for the
+    /// nodes of the last generation, and for tokens that were erased.
+    pub fn children(&self) -> Range<usize> {
+        self.children.clone()
+    }
+
+    /// Returns `true` if a production rule was applied to this node.  When rewriting in
+    /// parallel every token is rewritten (tokens without rules by the rule `P => P`), while a
+    /// sequential [`RewriteMode`](crate::system::RewriteMode) leaves most tokens alone.  This is
+    /// always `false` for the nodes of the last generation.
+    pub fn is_rewritten(&self) -> bool {
+        self.rewritten
+    }
+}
+
+/// The derivation tree of a system, from its axiom up to a given generation.  See the [module
+/// level documentation](self) for details.
+#[derive(Clone, Debug)]
+pub struct Derivation {
+    // The nodes of every generation, one generation after another
+    nodes: Vec<DerivationNode>,
+
+    // The range of `nodes` holding each generation
+    generations: Vec<Range<usize>>,
+}
+
+impl Derivation {
+    /// Derive the first `depth` generations of `system` from its axiom, recording how each
+    /// token was produced.  The choices made by stochastic rules are the same as those made by
+    /// [`LSystem::expanded()`].
+    pub fn new(system: &LSystem, depth: usize) -> Self {
+        let mut nodes: Vec<_> = system
+            .get_axiom()
+            .iter()
+            .map(|&token| DerivationNode {
+                token,
+                generation: 0,
+                parent: None,
+                children: 0..0,
+                rewritten: false,
+            })
+            .collect();
+        let mut generations = Vec::with_capacity(depth + 1);
+        generations.push(0..nodes.len());
+        let mut state = system.get_axiom().to_vec();
+
+        for generation in 0..depth {
+            let start = generations[generation].start;
+            let mut next_state = Vec::with_capacity(state.len());
+            let mut children = Vec::with_capacity(state.len());
+
+            for (position, successor) in system.successors_of(&state, generation).enumerate() {
+                let first = next_state.len();
+
+                match successor {
+                    Some(successor) => next_state.extend_from_slice(successor),
+                    None => next_state.push(state[position]),
+                }
+
+                children.push((successor.is_some(), first..next_state.len()));
+            }
+
+            let end = nodes.len();
+
+            for (position, (rewritten, range)) in children.into_iter().enumerate() {
+                let node = &mut nodes[start + position];
+                node.rewritten = rewritten;
+                node.children = end + range.start..end + range.end;
+
+                nodes.extend(range.map(|child| DerivationNode {
+                    token: next_state[child],
+                    generation: generation + 1,
+                    parent: Some(start + position),
+                    children: 0..0,
+                    rewritten: false,
+                }));
+            }
+
+            generations.push(end..nodes.len());
+            state = next_state;
+        }
+
+        Self { nodes, generations }
+    }
+
+    /// Returns the number of generations after the axiom held by this derivation.
+    pub fn depth(&self) -> usize {
+        self.generations.len() - 1
+    }
+
+    /// Returns the total number of nodes in this derivation, over every generation.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Returns `true` if this derivation has no nodes.
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Returns the node with the given index, if there is one.
+    pub fn get(&self, index: usize) -> Option<&DerivationNode> {
+        self.nodes.get(index)
+    }
+
+    /// Returns the indices of the nodes of generation `generation`, in order.
+    ///
+    /// # Panics
+    /// Panics if `generation` is greater than [`Derivation::depth()`].
+    pub fn generation(&self, generation: usize) -> Range<usize> {
+        self.generations[generation].clone()
+    }
+
+    /// Returns the index of the parent of the node `index`, followed by the index of its
+    /// parent, and so on back to a token of the axiom.
+    ///
+    /// # Panics
+    /// Panics if `index` isn't the index of a node.
+    pub fn ancestors(&self, index: usize) -> impl Iterator<Item = usize> + '_ {
+        std::iter::successors(self.nodes[index].parent, move |&parent| {
+            self.nodes[parent].parent
+        })
+    }
+
+    /// Returns the position within the axiom of the token that the node `index` descends
+    /// from.
+    ///
+    /// # Panics
+    /// Panics if `index` isn't the index of a node.
+    pub fn root(&self, index: usize) -> usize {
+        // The nodes of the axiom come first, so the index of a root is its position
+        self.ancestors(index).last().unwrap_or(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::LSystemBuilder;
+    use crate::errors::LSystemError;
+    use crate::system::RewriteMode;
+
+    #[test]
+    fn test_derivation_matches_expanded() -> Result<(), LSystemError> {
+        for mode in [RewriteMode::Parallel, RewriteMode::SequentialFirst] {
+            let mut builder = LSystemBuilder::new();
+            let a = builder.token("a")?;
+            let b = builder.token("b")?;
+            builder.axiom(vec![a, b, a])?;
+            builder.stochastic_rule(a, vec![(1.0, vec![a, b]), (1.0, vec![])])?;
+            builder.rewrite_mode(mode);
+            builder.seed(3);
+            let system = builder.finish()?;
+
+            let derivation = Derivation::new(&system, 6);
+            assert_eq!(derivation.depth(), 6);
+
+            for generation in 0..=6 {
+                let tokens: Vec<_> = derivation
+                    .generation(generation)
+                    .map(|index| derivation.get(index).unwrap().token())
+                    .collect();
+                assert_eq!(tokens, system.expanded(generation));
+            }
+
+            // Every node is one of the children of its parent
+            for index in derivation.generation(1).start..derivation.len() {
+                let node = derivation.get(index).unwrap();
+                let parent = derivation.get(node.parent().unwrap()).unwrap();
+                assert!(parent.children().contains(&index));
+                assert_eq!(parent.generation() + 1, node.generation());
+            }
+
+            // The `b` in the middle of the axiom has no rules, so it is all that descends
+            // from it, and a sequential system never rewrites it
+            let middle: Vec<_> = derivation
+                .generation(6)
+                .filter(|&index| derivation.root(index) == 1)
+                .collect();
+            assert_eq!(middle.len(), 1);
+            assert_eq!(derivation.get(middle[0]).unwrap().token(), b);
+            assert_eq!(derivation.ancestors(middle[0]).count(), 6);
+            assert!(derivation.ancestors(middle[0]).all(|index| {
+                derivation.get(index).unwrap().is_rewritten() == (mode == RewriteMode::Parallel)
+            }));
+        }
+
+        Ok(())
+    }
+}
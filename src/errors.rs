@@ -1,3 +1,5 @@
+use std::ops::Range;
+
 use crate::ArenaId;
 use thiserror::Error;
 
@@ -12,8 +14,37 @@ pub enum LSystemError {
     InvalidArenaId(ArenaId),
     #[error("invalid rule `{0}`")]
     InvalidRule(String),
+    #[error("malformed input `{input}` at bytes {}..{}: {reason}", span.start, span.end)]
+    MalformedRule {
+        /// The offending part of the input.
+        input: String,
+
+        /// The byte range of `input` within the text that was parsed.
+        span: Range<usize>,
+
+        /// What is wrong with `input`.
+        reason: String,
+    },
+    #[error("token `{0}` has more than one transformation rule")]
+    DuplicateRule(String),
+    #[error("unknown rule table `{0}`")]
+    UnknownTable(String),
+    #[error("invalid weight `{0}`, weights must be positive and finite")]
+    InvalidWeight(f64),
     #[error("axiom has not been defined")]
     MissingAxiom,
+    #[error("axiom must contain at least one token")]
+    EmptyAxiom,
+    #[error("failed to allocate memory for the next generation")]
+    AllocationFailed(#[from] std::collections::TryReserveError),
+    #[error("generation {generation} would have {len} tokens, more than the limit of {limit}")]
+    SymbolLimitExceeded {
+        generation: usize,
+        len: usize,
+        limit: usize,
+    },
+    #[error("token `{token}` contains the separator `{separator}`")]
+    AmbiguousSeparator { token: String, separator: String },
     #[error("io error")]
     IOError(#[from] std::io::Error),
     #[error("there was an unexpected error in another thread")]
@@ -24,12 +55,3 @@ pub enum LSystemError {
         source: Box<dyn std::error::Error + Send + Sync>,
     },
 }
-
-#[cfg(feature = "image_renderer")]
-impl From<gifski::Error> for LSystemError {
-    fn from(e: gifski::Error) -> Self {
-        LSystemError::Other {
-            source: Box::new(e),
-        }
-    }
-}
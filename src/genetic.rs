@@ -0,0 +1,383 @@
+//! Genetic operators for evolving [`LSystemBuilder`]s.
+//!
+//! The operators in this module work on builders rather than on built systems, so that the
+//! offspring can be further tweaked (or mutated again) before calling
+//! [`LSystemBuilder::finish()`].
+//!
+//! # Example
+//! ```rust
+//! # use dcc_lsystem::LSystemError;
+//! # fn main() -> Result<(), LSystemError> {
+//! use dcc_lsystem::LSystemBuilder;
+//! use dcc_lsystem::genetic::{crossover, mutate, perturb_weights, swap_tokens};
+//! use dcc_lsystem::rng::Rng;
+//!
+//! let mut first = LSystemBuilder::new();
+//! let a = first.token("a")?;
+//! let b = first.token("b")?;
+//! first.axiom(vec![a])?;
+//! first.transformation_rule(a, vec![a, b])?;
+//! first.transformation_rule(b, vec![a])?;
+//!
+//! let mut second = first.clone();
+//! second.rules.clear();
+//! second.transformation_rule(a, vec![b, b])?;
+//!
+//! let mut rng = Rng::new(5);
+//! let (child, _) = crossover(&first, &second, &mut rng)?;
+//! let child = mutate(&child, 0.1, &mut rng);
+//! let child = swap_tokens(&child, 0.1, &mut rng);
+//! let child = perturb_weights(&child, 0.2, &mut rng);
+//!
+//! let mut system = child.finish()?;
+//! system.step_by(3);
+//! # Ok(())
+//! # }
+//! ```
+use crate::arena::ArenaId;
+use crate::builder::{LSystemBuilder, TransformationRule};
+use crate::errors::LSystemError;
+use crate::rng::Rng;
+
+/// Performs a crossover of the rule sets of two builders.
+///
+/// For every token that acts as a predecessor in either builder, a coin is flipped; on heads the
+/// two builders swap all of their rules for that token.  Tokens are matched between the two
+/// builders by name, so both builders need to share an alphabet.  Each child keeps the arena and
+/// axiom of the corresponding parent.
+///
+/// Returns an [`LSystemError::UnknownToken`] error if a swapped rule refers to a token that
+/// isn't registered in the receiving builder.
+///
+/// # Example
+/// ```rust
+/// # use dcc_lsystem::LSystemError;
+/// # fn main() -> Result<(), LSystemError> {
+/// use dcc_lsystem::LSystemBuilder;
+/// use dcc_lsystem::genetic::crossover;
+/// use dcc_lsystem::rng::Rng;
+///
+/// let mut first = LSystemBuilder::new();
+/// let a = first.token("a")?;
+/// first.axiom(vec![a])?;
+/// first.transformation_rule(a, vec![a, a])?;
+///
+/// let mut second = LSystemBuilder::new();
+/// let a = second.token("a")?;
+/// second.axiom(vec![a])?;
+/// second.transformation_rule(a, vec![a, a, a])?;
+///
+/// let (x, y) = crossover(&first, &second, &mut Rng::new(3))?;
+///
+/// // Between them, the children still have both of the original rules.
+/// let mut lengths = vec![x.rules[0].successor().len(), y.rules[0].successor().len()];
+/// lengths.sort();
+/// assert_eq!(lengths, vec![2, 3]);
+/// # Ok(())
+/// # }
+/// ```
+pub fn crossover(
+    first: &LSystemBuilder,
+    second: &LSystemBuilder,
+    rng: &mut Rng,
+) -> Result<(LSystemBuilder, LSystemBuilder), LSystemError> {
+    // Collect the names of all predecessors, in a deterministic order.
+    let mut names: Vec<&str> = Vec::new();
+
+    for (builder, rule) in first
+        .rules
+        .iter()
+        .map(|rule| (first, rule))
+        .chain(second.rules.iter().map(|rule| (second, rule)))
+    {
+        let name = token_name(builder, rule.predecessor());
+
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+
+    let mut first_child = first.clone();
+    let mut second_child = second.clone();
+    first_child.rules.clear();
+    second_child.rules.clear();
+
+    for name in names {
+        let (first_source, second_source) = if rng.gen_index(2) == 0 {
+            (first, second)
+        } else {
+            (second, first)
+        };
+
+        for rule in rules_for(first_source, name) {
+            let rule = translate(rule, first_source, &first_child)?;
+            first_child.rules.push(rule);
+        }
+
+        for rule in rules_for(second_source, name) {
+            let rule = translate(rule, second_source, &second_child)?;
+            second_child.rules.push(rule);
+        }
+    }
+
+    Ok((first_child, second_child))
+}
+
+/// Returns a mutated copy of `builder`.
+///
+/// Each token in the successor of each rule is mutated with probability `rate`.  A mutation
+/// either replaces the token with a random token from the builder's alphabet, deletes it, or
+/// inserts a random token directly after it, each with equal probability.
+///
+/// # Example
+/// ```rust
+/// # use dcc_lsystem::LSystemError;
+/// # fn main() -> Result<(), LSystemError> {
+/// use dcc_lsystem::LSystemBuilder;
+/// use dcc_lsystem::genetic::mutate;
+/// use dcc_lsystem::rng::Rng;
+///
+/// let mut builder = LSystemBuilder::new();
+/// let a = builder.token("a")?;
+/// let b = builder.token("b")?;
+/// builder.axiom(vec![a])?;
+/// builder.transformation_rule(a, vec![a, b, a])?;
+///
+/// // A mutation rate of zero leaves the rules untouched
+/// let unchanged = mutate(&builder, 0.0, &mut Rng::new(1));
+/// assert_eq!(unchanged.rules[0].successor(), &[a, b, a]);
+///
+/// // ...but a mutation rate of one changes every token
+/// let mutated = mutate(&builder, 1.0, &mut Rng::new(1));
+/// assert_ne!(mutated.rules[0].successor(), &[a, b, a]);
+/// # Ok(())
+/// # }
+/// ```
+pub fn mutate(builder: &LSystemBuilder, rate: f64, rng: &mut Rng) -> LSystemBuilder {
+    let mut mutated = builder.clone();
+    let alphabet = builder.arena.len();
+
+    if alphabet == 0 {
+        return mutated;
+    }
+
+    for rule in mutated.rules.iter_mut() {
+        let mut successor = Vec::with_capacity(rule.successor().len());
+
+        for &id in rule.successor() {
+            if rng.gen_f64() >= rate {
+                successor.push(id);
+                continue;
+            }
+
+            match rng.gen_index(3) {
+                // Replace
+                0 => successor.push(ArenaId(rng.gen_index(alphabet))),
+                // Delete
+                1 => {}
+                // Insert
+                _ => {
+                    successor.push(id);
+                    successor.push(ArenaId(rng.gen_index(alphabet)));
+                }
+            }
+        }
+
+        *rule = rule.remade(rule.predecessor(), successor, rule.weight());
+    }
+
+    mutated
+}
+
+/// Returns a copy of `builder` with tokens in the successors of its rules swapped around.
+///
+/// Each pair of neighbouring tokens in the successor of each rule is swapped with probability
+/// `rate`.  Unlike [`mutate()`], this never changes which tokens a successor contains, only
+/// their order.
+///
+/// # Example
+/// ```rust
+/// # use dcc_lsystem::LSystemError;
+/// # fn main() -> Result<(), LSystemError> {
+/// use dcc_lsystem::LSystemBuilder;
+/// use dcc_lsystem::genetic::swap_tokens;
+/// use dcc_lsystem::rng::Rng;
+///
+/// let mut builder = LSystemBuilder::new();
+/// let a = builder.token("a")?;
+/// let b = builder.token("b")?;
+/// builder.axiom(vec![a])?;
+/// builder.transformation_rule(a, vec![a, b])?;
+///
+/// let swapped = swap_tokens(&builder, 1.0, &mut Rng::new(1));
+/// assert_eq!(swapped.rules[0].successor(), &[b, a]);
+/// # Ok(())
+/// # }
+/// ```
+pub fn swap_tokens(builder: &LSystemBuilder, rate: f64, rng: &mut Rng) -> LSystemBuilder {
+    let mut swapped = builder.clone();
+
+    for rule in swapped.rules.iter_mut() {
+        let mut successor = rule.successor().to_vec();
+
+        for i in 1..successor.len() {
+            if rng.gen_f64() < rate {
+                successor.swap(i - 1, i);
+            }
+        }
+
+        *rule = rule.remade(rule.predecessor(), successor, rule.weight());
+    }
+
+    swapped
+}
+
+/// Returns a copy of `builder` with the weights of its rules randomly scaled.
+///
+/// Each weight is multiplied by `e^x`, where `x` is chosen uniformly from `[-scale, scale)`,
+/// so weights stay positive and are as likely to shrink as to grow.  Only the relative
+/// weights of the rules for the same token matter, so this changes how often each
+/// alternative of a stochastic rule is chosen.
+///
+/// The new weights are clamped to the finite positive range `[f64::MIN_POSITIVE, f64::MAX]`,
+/// however large `scale` is.  If `scale` is NaN the weights are left as they are.
+///
+/// # Example
+/// ```rust
+/// # use dcc_lsystem::LSystemError;
+/// # fn main() -> Result<(), LSystemError> {
+/// use dcc_lsystem::LSystemBuilder;
+/// use dcc_lsystem::genetic::perturb_weights;
+/// use dcc_lsystem::rng::Rng;
+///
+/// let mut builder = LSystemBuilder::new();
+/// let a = builder.token("a")?;
+/// let b = builder.token("b")?;
+/// builder.axiom(vec![a])?;
+/// builder.stochastic_rule(a, vec![(1.0, vec![a, b]), (1.0, vec![b, a])])?;
+///
+/// let perturbed = perturb_weights(&builder, 0.5, &mut Rng::new(1));
+/// for rule in perturbed.rules.iter() {
+///     assert!(rule.weight() >= (-0.5f64).exp() && rule.weight() < 0.5f64.exp());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn perturb_weights(builder: &LSystemBuilder, scale: f64, rng: &mut Rng) -> LSystemBuilder {
+    let mut perturbed = builder.clone();
+
+    for rule in perturbed.rules.iter_mut() {
+        let factor = (scale * (2.0 * rng.gen_f64() - 1.0)).exp();
+        let weight = match rule.weight() * factor {
+            weight if weight.is_nan() => rule.weight(),
+            weight => weight.clamp(f64::MIN_POSITIVE, f64::MAX),
+        };
+
+        *rule = rule.remade(rule.predecessor(), rule.successor().to_vec(), weight);
+    }
+
+    perturbed
+}
+
+fn token_name(builder: &LSystemBuilder, id: ArenaId) -> &str {
+    // unwrap: the builder verifies the ids of every rule it is given.
+    builder.arena.get(id).unwrap().name()
+}
+
+fn rules_for<'a>(
+    builder: &'a LSystemBuilder,
+    name: &'a str,
+) -> impl Iterator<Item = &'a TransformationRule> + 'a {
+    builder
+        .rules
+        .iter()
+        .filter(move |rule| token_name(builder, rule.predecessor()) == name)
+}
+
+/// Rewrites `rule` (a rule of `source`) in terms of the tokens of `target`.
+fn translate(
+    rule: &TransformationRule,
+    source: &LSystemBuilder,
+    target: &LSystemBuilder,
+) -> Result<TransformationRule, LSystemError> {
+    let lookup = |id: ArenaId| {
+        let name = token_name(source, id);
+
+        target
+            .get_token(name)
+            .ok_or_else(|| LSystemError::UnknownToken(name.to_string()))
+    };
+
+    let predecessor = lookup(rule.predecessor())?;
+    let successor = rule
+        .successor()
+        .iter()
+        .map(|&id| lookup(id))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(rule.remade(predecessor, successor, rule.weight()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crossover_unknown_token() -> Result<(), LSystemError> {
+        let mut first = LSystemBuilder::new();
+        let a = first.token("a")?;
+        let b = first.token("b")?;
+        first.transformation_rule(a, vec![b])?;
+
+        let mut second = LSystemBuilder::new();
+        let a = second.token("a")?;
+        second.transformation_rule(a, vec![a])?;
+
+        // `second` has no token named `b`, so at least one of these seeds
+        // has to try swapping the rule `a => b` into it.
+        let results: Vec<_> = (0..8)
+            .map(|seed| crossover(&first, &second, &mut Rng::new(seed)))
+            .collect();
+
+        assert!(results.iter().any(|result| result.is_err()));
+        assert!(results.iter().any(|result| result.is_ok()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mutate_keeps_ids_valid() -> Result<(), LSystemError> {
+        let mut builder = LSystemBuilder::new();
+        let a = builder.token("a")?;
+        let b = builder.token("b")?;
+        builder.axiom(vec![a])?;
+        builder.transformation_rule(a, vec![a, b, a, b])?;
+
+        let mut rng = Rng::new(99);
+
+        for _ in 0..50 {
+            builder = mutate(&builder, 0.5, &mut rng);
+            assert!(builder.arena.is_valid_slice(builder.rules[0].successor()));
+        }
+
+        // Swapping and reweighting keep the rules valid too
+        builder = swap_tokens(&builder, 0.5, &mut rng);
+        builder = perturb_weights(&builder, 2.0, &mut rng);
+        assert!(builder.arena.is_valid_slice(builder.rules[0].successor()));
+        assert!(builder.rules[0].weight() > 0.0);
+
+        // However extreme the scale, the weights stay finite and positive
+        for scale in [1e6, f64::INFINITY, f64::NAN] {
+            let perturbed = perturb_weights(&builder, scale, &mut rng);
+            let weight = perturbed.rules[0].weight();
+            assert!(weight.is_finite() && weight > 0.0);
+            assert!(perturbed.finish().is_ok());
+        }
+
+        // The mutated builder must still produce a working system.
+        let mut system = builder.finish()?;
+        system.step();
+
+        Ok(())
+    }
+}
@@ -0,0 +1,528 @@
+//! A plain text format for describing L-systems.
+//!
+//! A grammar file describes the alphabet, axiom and production rules of a system, one item
+//! per line.  Tokens are separated by whitespace.
+//!
+//! ```text
+//! # Lindenmayer's system for modelling the growth of algae
+//! alphabet: A B
+//! axiom: A
+//! A => A B
+//! B => A
+//! ```
+//!
+//! * Blank lines and lines starting with `#` are ignored.
+//! * `alphabet:` lists the tokens of the system.  If there's no alphabet line, tokens are
+//!   registered as they are first used; otherwise using a token that isn't in the alphabet is
+//!   an error.
+//! * Tokens that are already registered don't need to be separated by whitespace.  A word
+//!   that isn't a token name, like `F+F-F`, is split up by repeatedly taking the longest
+//!   registered token name at the start of what remains, so with an alphabet of `F F1 +` the
+//!   run `F1F+` is `F1 F +`.  Without an alphabet, a word that can't be split up that way is
+//!   registered as a new token; with one, it is an error.
+//! * `axiom:` sets the axiom.
+//! * `interpretation: predecessor => tokens` sets the interpretation of a token (see
+//!   [`LSystemBuilder::interpretation()`]), which is only applied when the state is drawn.
+//! * Every other line is a production rule `predecessor => successor`.  The successor may be
+//!   empty, in which case the predecessor is erased.
+//! * A rule may end with a weight, as in `F => F F : 0.4`.  All the weighted rules for a
+//!   token form a single stochastic rule (see [`LSystemBuilder::stochastic_rule()`]), with
+//!   each successor chosen in proportion to its weight.  Weights must be positive and finite,
+//!   and a token's rules must either all have weights or none of them.  A `:` that isn't followed by a number at the end of the rule
+//!   is an ordinary token, as in `F => F :`.
+//!
+//! Mistakes in a grammar are reported as [`LSystemError::MalformedRule`] errors, which give
+//! the byte range of the offending text within the grammar.
+//!
+//! Grammars are loaded with [`LSystemBuilder::from_str()`](std::str::FromStr) or
+//! [`LSystemBuilder::from_path()`], which return a builder that can be tweaked further
+//! before calling [`LSystemBuilder::finish()`].
+//!
+//! # Example
+//! ```rust
+//! # use dcc_lsystem::LSystemError;
+//! # fn main() -> Result<(), LSystemError> {
+//! use dcc_lsystem::LSystemBuilder;
+//!
+//! let builder: LSystemBuilder = "
+//!     alphabet: A B
+//!     axiom: A
+//!     A => A B
+//!     B => A
+//! "
+//! .parse()?;
+//!
+//! let mut system = builder.finish()?;
+//! system.step_by(4);
+//! assert_eq!(system.render(), "ABAABABA");
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Koch's curve, written in the classic notation without whitespace:
+//! ```rust
+//! # use dcc_lsystem::{LSystemBuilder, LSystemError};
+//! # fn main() -> Result<(), LSystemError> {
+//! let builder: LSystemBuilder = "
+//!     alphabet: F + -
+//!     axiom: F
+//!     F=>F+F-F-F+F
+//! "
+//! .parse()?;
+//!
+//! let mut system = builder.finish()?;
+//! system.step();
+//! assert_eq!(system.render(), "F+F-F-F+F");
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! A stochastic grammar, where `F` doubles 40% of the time:
+//! ```rust
+//! # use dcc_lsystem::{LSystemBuilder, LSystemError};
+//! # fn main() -> Result<(), LSystemError> {
+//! let builder: LSystemBuilder = "
+//!     axiom: F
+//!     F => F F : 0.4
+//!     F => F : 0.6
+//! "
+//! .parse()?;
+//!
+//! let mut system = builder.finish()?;
+//! system.step_by(5);
+//! assert!(system.get_state().len() <= 32);
+//! # Ok(())
+//! # }
+//! ```
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::arena::ArenaId;
+use crate::builder::LSystemBuilder;
+use crate::errors::LSystemError;
+
+impl LSystemBuilder {
+    /// Load a builder from the grammar file at `path`.  See the [`grammar`](crate::grammar)
+    /// module for a description of the format.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, LSystemError> {
+        std::fs::read_to_string(path)?.parse()
+    }
+
+    /// Set the axiom of this builder from a string of whitespace separated token names, as in
+    /// the `axiom:` line of a [grammar](crate::grammar).  Runs of registered tokens, like
+    /// `F+F`, don't need to be separated, and any other tokens that haven't been registered
+    /// yet are registered.
+    ///
+    /// This function will return an [`LSystemError::EmptyAxiom`] error if `axiom` has no
+    /// tokens.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::LSystemError;
+    /// # fn main() -> Result<(), LSystemError> {
+    /// use dcc_lsystem::LSystemBuilder;
+    ///
+    /// let mut builder = LSystemBuilder::new();
+    /// builder.axiom_str("A")?;
+    /// builder.rule_str("A => A B")?;
+    /// builder.rule_str("B => A")?;
+    ///
+    /// let mut system = builder.finish()?;
+    /// system.step_by(4);
+    /// assert_eq!(system.render(), "ABAABABA");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn axiom_str(&mut self, axiom: &str) -> Result<(), LSystemError> {
+        let axiom = self.parse_tokens(0, axiom, false)?;
+        self.axiom(axiom)
+    }
+
+    /// Register a transformation rule given as a string `predecessor => successor`, as in a
+    /// [grammar](crate::grammar), where the predecessor is a single token name and the
+    /// successor is a (possibly empty) list of whitespace separated token names.  Runs of
+    /// registered tokens don't need to be separated, so the classic `F=>F+F-F-F+F` works once
+    /// `F`, `+` and `-` are registered.  Any other tokens that haven't been registered yet are
+    /// registered.  See [`LSystemBuilder::transformation_rule()`] for details.
+    ///
+    /// This function will return an [`LSystemError::MalformedRule`] error if `rule` isn't of
+    /// that form.  Weighted rules aren't accepted; use
+    /// [`LSystemBuilder::stochastic_rule()`] instead.
+    pub fn rule_str(&mut self, rule: &str) -> Result<(), LSystemError> {
+        let (predecessor, successor, weight) = self.parse_rule(0, rule, false)?;
+
+        if weight.is_some() {
+            return Err(malformed(
+                0,
+                rule,
+                "weighted rules must be added with `stochastic_rule`",
+            ));
+        }
+
+        self.transformation_rule(predecessor, successor)
+    }
+
+    /// Returns the ids of the whitespace separated tokens in `text`, which starts `offset`
+    /// bytes into the source being parsed.  Words that aren't tokens are split into runs of
+    /// registered tokens where possible.  Otherwise they are registered as new tokens, unless
+    /// `alphabet_fixed` is set, in which case they are an [`LSystemError::MalformedRule`]
+    /// error.
+    pub(crate) fn parse_tokens(
+        &mut self,
+        offset: usize,
+        text: &str,
+        alphabet_fixed: bool,
+    ) -> Result<Vec<ArenaId>, LSystemError> {
+        let mut ids = Vec::new();
+
+        for (offset, word) in words(offset, text) {
+            if let Some(id) = self.get_token(word) {
+                ids.push(id);
+            } else if let Some(run) = self.split_run(word) {
+                ids.extend(run);
+            } else if alphabet_fixed {
+                return Err(malformed(offset, word, "unknown token"));
+            } else {
+                ids.push(self.token(word)?);
+            }
+        }
+
+        Ok(ids)
+    }
+
+    /// Splits `run` into registered tokens, by repeatedly taking the longest token name that
+    /// `run` starts with.  Returns `None` if some part of `run` doesn't start with a token.
+    fn split_run(&self, run: &str) -> Option<Vec<ArenaId>> {
+        let mut ids = Vec::new();
+        let mut rest = run;
+
+        while !rest.is_empty() {
+            // Later tokens win ties, to agree with `get_token`
+            let (id, len) = self
+                .arena
+                .enumerate()
+                .map(|(id, token)| (id, token.name()))
+                .filter(|(_, name)| !name.is_empty() && rest.starts_with(name))
+                .map(|(id, name)| (id, name.len()))
+                .fold(None, |best, (id, len)| match best {
+                    Some((_, best_len)) if best_len > len => best,
+                    _ => Some((id, len)),
+                })?;
+
+            ids.push(id);
+            rest = &rest[len..];
+        }
+
+        Some(ids)
+    }
+
+    /// Parses a production rule `predecessor => successor`, optionally followed by a weight
+    /// `: weight` where `weight` is a number, returning the ids of the predecessor and successor
+    /// and the weight (if any).  The rule starts `offset` bytes into the source being parsed.
+    pub(crate) fn parse_rule(
+        &mut self,
+        offset: usize,
+        rule: &str,
+        alphabet_fixed: bool,
+    ) -> Result<(ArenaId, Vec<ArenaId>, Option<f64>), LSystemError> {
+        let (predecessor, successor) = rule
+            .split_once("=>")
+            .ok_or_else(|| malformed(offset, rule, "expected `=>`"))?;
+        let successor_offset = offset + predecessor.len() + "=>".len();
+
+        // A weight is introduced by a `:` that isn't part of a token, and is followed by a
+        // number at the end of the rule.  Any other `:` is just a token.
+        let (successor, weight) = match successor.rsplit_once(':') {
+            Some((rest, weight)) if rest.is_empty() || rest.ends_with(char::is_whitespace) => {
+                let (weight_offset, text) = trimmed(successor_offset + rest.len() + 1, weight);
+
+                match text.parse::<f64>() {
+                    Ok(weight) if !(weight.is_finite() && weight > 0.0) => {
+                        return Err(malformed(
+                            weight_offset,
+                            text,
+                            "weights must be positive and finite",
+                        ))
+                    }
+                    Ok(weight) => (rest, Some(weight)),
+                    Err(_) => (successor, None),
+                }
+            }
+            _ => (successor, None),
+        };
+
+        let (predecessor_offset, predecessor) = trimmed(offset, predecessor);
+        let predecessor =
+            match self.parse_tokens(predecessor_offset, predecessor, alphabet_fixed)?[..] {
+                [id] => id,
+                _ => {
+                    return Err(malformed(
+                        predecessor_offset,
+                        predecessor,
+                        "expected a single predecessor token",
+                    ))
+                }
+            };
+
+        Ok((
+            predecessor,
+            self.parse_tokens(successor_offset, successor, alphabet_fixed)?,
+            weight,
+        ))
+    }
+}
+
+impl FromStr for LSystemBuilder {
+    type Err = LSystemError;
+
+    /// Parses a grammar.  See the [`grammar`](crate::grammar) module for a description of the
+    /// format.
+    fn from_str(grammar: &str) -> Result<Self, Self::Err> {
+        let mut builder = LSystemBuilder::new();
+        let mut alphabet_fixed = false;
+
+        // The weighted rules of each token, in the order the tokens first appear
+        let mut weighted: Vec<(ArenaId, Vec<_>)> = Vec::new();
+
+        let mut line_offset = 0;
+
+        for raw_line in grammar.split_inclusive('\n') {
+            let (offset, line) = trimmed(line_offset, raw_line);
+            line_offset += raw_line.len();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some(alphabet) = line.strip_prefix("alphabet:") {
+                if alphabet_fixed || !builder.arena.is_empty() {
+                    return Err(malformed(
+                        offset,
+                        line,
+                        "the alphabet must be given once, before it is used",
+                    ));
+                }
+
+                for name in alphabet.split_whitespace() {
+                    builder.token(name)?;
+                }
+
+                alphabet_fixed = true;
+            } else if let Some(rule) = line.strip_prefix("interpretation:") {
+                let rule_offset = offset + "interpretation:".len();
+                let (id, tokens, weight) = builder.parse_rule(rule_offset, rule, alphabet_fixed)?;
+
+                if weight.is_some() {
+                    return Err(malformed(
+                        offset,
+                        line,
+                        "interpretations can't have weights",
+                    ));
+                }
+
+                builder.interpretation(id, tokens)?;
+            } else if let Some(axiom) = line.strip_prefix("axiom:") {
+                let axiom_offset = offset + "axiom:".len();
+                let axiom = builder.parse_tokens(axiom_offset, axiom, alphabet_fixed)?;
+                builder.axiom(axiom)?;
+            } else {
+                let (predecessor, successor, weight) =
+                    builder.parse_rule(offset, line, alphabet_fixed)?;
+                let group = weighted.iter_mut().find(|(id, _)| *id == predecessor);
+                let unweighted = builder
+                    .rules
+                    .iter()
+                    .any(|rule| rule.predecessor() == predecessor);
+
+                match (weight, group) {
+                    (Some(_), _) if unweighted => return Err(mixed_weights(offset, line)),
+                    (Some(weight), Some((_, choices))) => choices.push((weight, successor)),
+                    (Some(weight), None) => weighted.push((predecessor, vec![(weight, successor)])),
+                    (None, Some(_)) => return Err(mixed_weights(offset, line)),
+                    (None, None) => builder.transformation_rule(predecessor, successor)?,
+                }
+            }
+        }
+
+        for (predecessor, choices) in weighted {
+            builder.stochastic_rule(predecessor, choices)?;
+        }
+
+        Ok(builder)
+    }
+}
+
+/// Returns an [`LSystemError::MalformedRule`] error pointing at `part`, which starts `offset`
+/// bytes into the source being parsed.
+pub(crate) fn malformed(offset: usize, part: &str, reason: &str) -> LSystemError {
+    LSystemError::MalformedRule {
+        input: part.to_string(),
+        span: offset..offset + part.len(),
+        reason: reason.to_string(),
+    }
+}
+
+fn mixed_weights(offset: usize, line: &str) -> LSystemError {
+    malformed(
+        offset,
+        line,
+        "the rules for a token must either all have weights or none",
+    )
+}
+
+/// Returns `text` (which starts `offset` bytes into the source) without leading and trailing
+/// whitespace, along with the offset it then starts at.
+fn trimmed(offset: usize, text: &str) -> (usize, &str) {
+    let start = text.len() - text.trim_start().len();
+    (offset + start, text.trim())
+}
+
+/// Returns the whitespace separated words of `text` (which starts `offset` bytes into the
+/// source), each along with the offset it starts at.
+fn words(offset: usize, text: &str) -> impl Iterator<Item = (usize, &str)> {
+    let mut rest = text;
+    let mut offset = offset;
+
+    std::iter::from_fn(move || {
+        let (start, word) = trimmed(offset, rest);
+        let len = word.find(char::is_whitespace).unwrap_or(word.len());
+
+        if len == 0 {
+            return None;
+        }
+
+        let (word, after) = rest[start - offset..].split_at(len);
+        offset = start + len;
+        rest = after;
+
+        Some((start, word))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grammar_errors() -> Result<(), LSystemError> {
+        let parse = |grammar: &str| grammar.parse::<LSystemBuilder>();
+
+        // Tokens outside the alphabet
+        let span = |grammar: &str| match parse(grammar) {
+            Err(LSystemError::MalformedRule { span, .. }) => Some(span),
+            _ => None,
+        };
+
+        assert_eq!(span("alphabet: A\naxiom: A B"), Some(21..22));
+        assert_eq!(span("alphabet: A\r\naxiom:\u{3000}A B"), Some(24..25));
+
+        // Rules without an arrow, or with more than one predecessor
+        assert_eq!(span("axiom: A\n  A B  \n"), Some(11..14));
+        assert_eq!(span("A B => A"), Some(0..3));
+
+        // An alphabet given after tokens are already in use
+        assert_eq!(span("axiom: A\nalphabet: A"), Some(9..20));
+        assert_eq!(span("axiom: A\r\n  alphabet: A"), Some(12..23));
+
+        // Empty axioms and successors
+        assert!(matches!(parse("axiom:"), Err(LSystemError::EmptyAxiom)));
+        assert!(parse("axiom: A\nA =>").is_ok());
+
+        // Runs of tokens are split by longest match once there's an alphabet
+        let mut builder = parse("alphabet: F F1 +\naxiom: F1F+ F1").unwrap();
+        let ids = |names: &[&str]| -> Vec<ArenaId> {
+            names
+                .iter()
+                .map(|name| builder.get_token(name).unwrap())
+                .collect()
+        };
+        assert_eq!(builder.axiom, Some(ids(&["F1", "F", "+", "F1"])));
+        let source = "F F+G";
+        assert!(matches!(
+            builder.parse_tokens(2, &source[2..], true),
+            Err(LSystemError::MalformedRule { span, .. }) if span == (2..5)
+        ));
+
+        // Interpretations
+        let system = parse("axiom: A B\ninterpretation: A => F F\nA => B")?.finish()?;
+        assert_eq!(system.render_interpreted(), "FFB");
+        assert_eq!(span("A => B\ninterpretation: A => B : 1"), Some(7..33));
+
+        // Weights
+        assert!(parse("axiom: A\nA => A : 2\nA => : 0.5").is_ok());
+        assert!(parse("axiom: A\nA => A:b").is_ok());
+
+        // A `:` without a number after it is a token
+        for (rule, successor) in [
+            ("A => A :", &["A", ":"][..]),
+            ("A => : A", &[":", "A"]),
+            ("A => A : heavy", &["A", ":", "heavy"]),
+            ("A => : : 2", &[":"]),
+        ] {
+            let mut builder = parse(rule)?;
+            let successor: Vec<_> = successor
+                .iter()
+                .map(|name| builder.get_token(name).unwrap())
+                .collect();
+            assert_eq!(builder.rules[0].successor(), successor.as_slice());
+
+            let weighted = rule.ends_with('2');
+            assert_eq!(builder.rules[0].weight(), if weighted { 2.0 } else { 1.0 });
+            builder.axiom_str("A")?;
+            assert!(builder.finish().is_ok());
+        }
+        assert_eq!(span("A => A : -1"), Some(9..11));
+        assert_eq!(span("axiom: A\nA => A :  inf "), Some(19..22));
+        assert_eq!(span("A => A :0"), Some(8..9));
+        assert_eq!(span("A => A : 1\nA => A"), Some(11..17));
+        assert_eq!(span("A => A\nA => A : 1"), Some(7..17));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_builder_str_methods() -> Result<(), LSystemError> {
+        let mut builder = LSystemBuilder::new();
+        let a = builder.token("A")?;
+        builder.axiom_str(" A B ")?;
+        builder.rule_str("B =>")?;
+
+        // `A` is reused, and `B` is registered by the axiom
+        let b = builder.get_token("B").unwrap();
+        assert_eq!(builder.axiom, Some(vec![a, b]));
+        assert!(builder.rules[0].successor().is_empty());
+
+        assert!(matches!(
+            builder.axiom_str("  "),
+            Err(LSystemError::EmptyAxiom)
+        ));
+        assert!(matches!(
+            builder.rule_str("A B => A"),
+            Err(LSystemError::MalformedRule { span, .. }) if span == (0..3)
+        ));
+        assert!(matches!(
+            builder.rule_str("A => B : 0.5"),
+            Err(LSystemError::MalformedRule { .. })
+        ));
+
+        // Runs of registered tokens are split up, without registering the run as a token
+        let mut builder = LSystemBuilder::new();
+        for name in ["F", "+", "-"] {
+            builder.token(name)?;
+        }
+        builder.axiom_str("F+F")?;
+        builder.rule_str("F=>F+F-F-F+F")?;
+        builder.rule_str("X => FY")?;
+        assert_eq!(builder.get_token("F+F-F-F+F"), None);
+        assert_eq!(builder.rules[0].successor().len(), 9);
+
+        // A word that can't be split up is a new token
+        assert!(builder.get_token("FY").is_some());
+
+        let mut system = builder.finish()?;
+        system.step();
+        assert_eq!(system.render(), "F+F-F-F+F+F+F-F-F+F");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,229 @@
+//! Experimental inference of D0L-systems from example generations.
+//!
+//! Given a few consecutive generations of some unknown D0L-system (a deterministic,
+//! context-free system rewritten in parallel), [`infer()`] searches for production rules that
+//! rewrite each generation into the next.  The first generation is taken to be the axiom.
+//!
+//! The search tries every way of splitting each generation among the tokens of the one before
+//! it, so it can take a long time for long generations over a large alphabet.  Where several
+//! sets of rules are consistent with the generations the first one found is returned, trying
+//! shorter successors first; more generations narrow the choice down.
+//!
+//! # Example
+//! ```rust
+//! # use dcc_lsystem::LSystemError;
+//! # fn main() -> Result<(), LSystemError> {
+//! use dcc_lsystem::infer::infer;
+//!
+//! let builder = infer(&["A", "AB", "ABA", "ABAAB"]).unwrap();
+//!
+//! let mut system = builder.finish()?;
+//! system.step_by(5);
+//! assert_eq!(system.render(), "ABAABABAABAAB");
+//!
+//! // No D0L-system rewrites `A` to both `AB` and `AA`
+//! assert!(infer(&["A", "AB", "AAAA"]).is_none());
+//! # Ok(())
+//! # }
+//! ```
+use crate::arena::ArenaId;
+use crate::builder::LSystemBuilder;
+
+/// Search for a D0L-system that rewrites each of `generations` into the next, returning a
+/// builder for it.  If the generations contain whitespace then tokens are separated by
+/// whitespace; otherwise every character is a token.
+///
+/// The builder has one token for each distinct token of the generations, registered in the
+/// order they first appear, the first generation as its axiom, and a rule for every token
+/// that the generations show being rewritten to something other than itself.  Tokens that
+/// only appear in the last generation get no rule.
+///
+/// Returns `None` if no D0L-system is consistent with the generations, or if the first
+/// generation is empty.
+pub fn infer<S: AsRef<str>>(generations: &[S]) -> Option<LSystemBuilder> {
+    let separated = generations
+        .iter()
+        .any(|generation| generation.as_ref().contains(char::is_whitespace));
+
+    let mut builder = LSystemBuilder::new();
+    let mut names: Vec<String> = Vec::new();
+
+    let generations: Vec<Vec<ArenaId>> = generations
+        .iter()
+        .map(|generation| {
+            let tokens: Vec<String> = if separated {
+                generation
+                    .as_ref()
+                    .split_whitespace()
+                    .map(String::from)
+                    .collect()
+            } else {
+                generation.as_ref().chars().map(String::from).collect()
+            };
+
+            tokens
+                .into_iter()
+                .map(|name| match names.iter().position(|other| *other == name) {
+                    Some(index) => ArenaId(index),
+                    None => {
+                        names.push(name.clone());
+                        // unwrap: the name can't contain a space
+                        builder.token(name).unwrap()
+                    }
+                })
+                .collect()
+        })
+        .collect();
+
+    let axiom = generations.first()?.clone();
+    builder.axiom(axiom).ok()?;
+
+    let mut search = Search {
+        generations: &generations,
+        successors: vec![None; names.len()],
+    };
+
+    if !search.solve(0, 0, 0) {
+        return None;
+    }
+
+    for (index, successor) in search.successors.into_iter().enumerate() {
+        match successor {
+            Some(successor) if successor != [ArenaId(index)] => {
+                // unwrap: every id comes from the builder
+                builder
+                    .transformation_rule(ArenaId(index), successor)
+                    .unwrap();
+            }
+            _ => {}
+        }
+    }
+
+    Some(builder)
+}
+
+/// A depth first search for the successor of each token.
+struct Search<'a> {
+    generations: &'a [Vec<ArenaId>],
+
+    // The successor chosen for each token so far, indexed by ArenaId
+    successors: Vec<Option<Vec<ArenaId>>>,
+}
+
+impl Search<'_> {
+    /// Continue the search from the token at `position` of generation `pair`, whose successor
+    /// starts at `offset` of generation `pair + 1`.  Returns `true` (leaving the successors in
+    /// place) if the rest of the generations can be matched.
+    fn solve(&mut self, mut pair: usize, mut position: usize, mut offset: usize) -> bool {
+        let generations = self.generations;
+
+        // Tokens whose successor is already known are matched without branching, so the
+        // recursion only goes as deep as the number of choices made
+        loop {
+            if pair + 1 >= generations.len() {
+                return true;
+            }
+
+            let source = &generations[pair];
+            let target = &generations[pair + 1];
+
+            if position == source.len() {
+                if offset != target.len() {
+                    return false;
+                }
+
+                pair += 1;
+                position = 0;
+                offset = 0;
+                continue;
+            }
+
+            let id = source[position];
+            let Some(successor) = self.successors[id.0].as_ref() else {
+                break;
+            };
+
+            let end = offset + successor.len();
+            if end > target.len()
+                || target[offset..end] != successor[..]
+                || end + self.known_len(pair, position + 1) > target.len()
+            {
+                return false;
+            }
+
+            position += 1;
+            offset = end;
+        }
+
+        let target = &generations[pair + 1];
+        let id = generations[pair][position];
+        let available = target.len() - offset;
+        let Some(longest) = available.checked_sub(self.known_len(pair, position + 1)) else {
+            return false;
+        };
+
+        for len in 0..=longest {
+            self.successors[id.0] = Some(target[offset..offset + len].to_vec());
+
+            if self.solve(pair, position + 1, offset + len) {
+                return true;
+            }
+        }
+
+        self.successors[id.0] = None;
+        false
+    }
+
+    /// Returns the total length of the known successors of the tokens of generation `pair`
+    /// from `position` onwards, which is a lower bound on the length of their expansion.
+    fn known_len(&self, pair: usize, position: usize) -> usize {
+        self.generations[pair][position..]
+            .iter()
+            .filter_map(|id| self.successors[id.0].as_ref())
+            .map(Vec::len)
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::LSystemError;
+
+    #[test]
+    fn test_infer_round_trip() -> Result<(), LSystemError> {
+        let mut builder = LSystemBuilder::new();
+        let f = builder.token("F")?;
+        let plus = builder.token("+")?;
+        let minus = builder.token("-")?;
+        builder.axiom(vec![f, plus, f])?;
+        builder.transformation_rule(f, vec![f, minus, f, plus, f])?;
+        let mut system = builder.finish()?;
+
+        let mut generations = vec![system.render()];
+        for _ in 0..2 {
+            system.step();
+            generations.push(system.render());
+        }
+
+        let mut inferred = infer(&generations).unwrap().finish()?;
+        system.step_by(2);
+        inferred.step_by(4);
+        assert_eq!(inferred.render(), system.render());
+
+        // Whitespace separates longer token names
+        let inferred = infer(&["Apex", "Apex Bud", "Apex Bud Apex"]).unwrap();
+        assert_eq!(inferred.arena.len(), 2);
+        assert_eq!(inferred.rules.len(), 2);
+
+        // A single generation says nothing about the rules
+        let inferred = infer(&["AB"]).unwrap();
+        assert!(inferred.rules.is_empty());
+
+        assert!(infer::<&str>(&[]).is_none());
+        assert!(infer(&["", "A"]).is_none());
+        assert!(infer(&["AB", "ABA", "AB"]).is_none());
+
+        Ok(())
+    }
+}
@@ -26,6 +26,8 @@ Put the following in your `Cargo.toml`:
 dcc-lsystem = "0.7"
 ```
 
+dcc-lsystem needs Rust 1.82 or later.
+
 ## [`LSystemBuilder`]
 
 An L-system is represented by an instance of [`LSystem`].  To create a barebones [`LSystem`],
@@ -67,6 +69,26 @@ fn main() -> Result<(), LSystemError> {
     Ok(())
 }
 ```
+
+For quick experiments the axiom and rules can also be given as strings of whitespace
+separated token names, with tokens registered as they are first used:
+
+```rust
+use dcc_lsystem::{LSystemBuilder, LSystemError};
+
+fn main() -> Result<(), LSystemError> {
+    let mut builder = LSystemBuilder::new();
+    builder.axiom_str("A")?;
+    builder.rule_str("A => A B")?;
+    builder.rule_str("B => A")?;
+
+    let mut system = builder.finish()?;
+    system.step_by(3);
+    assert_eq!(system.render(), "ABAAB");
+
+    Ok(())
+}
+```
 ## License
 
 Licensed under either of
@@ -87,16 +109,28 @@ dual licensed as above, without any additional terms or conditions.
 
 extern crate self as dcc_lsystem;
 
-pub use arena::{Arena, ArenaId};
+pub use arena::{Arena, ArenaId, Remapping};
 pub use builder::LSystemBuilder;
 pub use errors::LSystemError;
 pub use system::LSystem;
 
+pub mod analysis;
 pub mod arena;
 pub mod builder;
+pub mod derivation;
 pub mod errors;
+pub mod genetic;
+pub mod grammar;
+pub mod infer;
+pub mod parametric;
+pub mod pool;
+pub mod rng;
+pub mod rope;
 pub mod system;
 pub mod token;
 
+mod splice;
+mod successors;
+
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,358 @@
+//! Parametric L-systems.
+//!
+//! In a parametric L-system every token in the state carries a list of numeric parameters,
+//! and production rules can read (and transform) the parameters of the token they rewrite.
+//! A token together with its parameters is called a [`Module`], and is rendered as
+//! `name(p1,p2,...)`.
+//!
+//! Production rules are ordinary closures, which take the parameters of the module being
+//! rewritten and return its successor.  A rule can also be guarded by a condition on the
+//! parameters, so that a token is only rewritten while (say) its length is above some
+//! threshold.
+//!
+//! # Example
+//! ```rust
+//! # use dcc_lsystem::LSystemError;
+//! # fn main() -> Result<(), LSystemError> {
+//! use dcc_lsystem::parametric::{Module, ParametricLSystemBuilder};
+//!
+//! let mut builder = ParametricLSystemBuilder::new();
+//! let f = builder.token("F")?;
+//! let plus = builder.token("+")?;
+//!
+//! builder.axiom(vec![Module::new(f, vec![10.0])])?;
+//!
+//! // F(x) => F(x/2) + F(x/2), for as long as x is at least 2
+//! builder.conditional_rule(
+//!     f,
+//!     |params| params[0] >= 2.0,
+//!     move |params| {
+//!         let half = params[0] / 2.0;
+//!         vec![
+//!             Module::new(f, vec![half]),
+//!             Module::new(plus, vec![]),
+//!             Module::new(f, vec![half]),
+//!         ]
+//!     },
+//! )?;
+//!
+//! let mut system = builder.finish()?;
+//! system.step_by(2)?;
+//! assert_eq!(system.render(), "F(2.5)+F(2.5)+F(2.5)+F(2.5)");
+//!
+//! // Once x drops below 2 the system stops growing
+//! system.step_by(2)?;
+//! assert_eq!(system.get_state().len(), 15);
+//! assert_eq!(system.get_state()[0], Module::new(f, vec![1.25]));
+//! # Ok(())
+//! # }
+//! ```
+use std::sync::Arc;
+
+use crate::arena::{Arena, ArenaId};
+use crate::errors::LSystemError;
+use crate::token::Token;
+
+/// A token together with its numeric parameters.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Module {
+    pub id: ArenaId,
+    pub params: Vec<f64>,
+}
+
+impl Module {
+    pub fn new(id: ArenaId, params: Vec<f64>) -> Self {
+        Self { id, params }
+    }
+}
+
+type Condition = Arc<dyn Fn(&[f64]) -> bool + Send + Sync>;
+type Production = Arc<dyn Fn(&[f64]) -> Vec<Module> + Send + Sync>;
+
+/// A production rule of a parametric L-system.
+#[derive(Clone)]
+struct ParametricRule {
+    predecessor: ArenaId,
+    condition: Option<Condition>,
+    production: Production,
+}
+
+impl ParametricRule {
+    fn applies(&self, params: &[f64]) -> bool {
+        self.condition
+            .as_ref()
+            .map(|condition| condition(params))
+            .unwrap_or(true)
+    }
+}
+
+impl std::fmt::Debug for ParametricRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        f.debug_struct("ParametricRule")
+            .field("predecessor", &self.predecessor)
+            .field("conditional", &self.condition.is_some())
+            .finish()
+    }
+}
+
+/// A struct for constructing [`ParametricLSystem`]s.
+#[derive(Debug, Default, Clone)]
+pub struct ParametricLSystemBuilder {
+    arena: Arena<Token>,
+    axiom: Option<Vec<Module>>,
+    rules: Vec<ParametricRule>,
+}
+
+impl ParametricLSystemBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a new token.
+    ///
+    /// Returns an [`ArenaId`] which can be used (in this system) to refer to the registered
+    /// token.
+    pub fn token<S: Into<String>>(&mut self, name: S) -> Result<ArenaId, LSystemError> {
+        Ok(self.arena.push(Token::new(name)?))
+    }
+
+    /// Set the axiom for this system.
+    ///
+    /// This function will return an [`LSystemError::EmptyAxiom`] error if the axiom contains
+    /// no modules, or an [`LSystemError::InvalidArenaId`] error if any of the modules refer to
+    /// an invalid token.
+    pub fn axiom(&mut self, axiom: Vec<Module>) -> Result<(), LSystemError> {
+        if axiom.is_empty() {
+            return Err(LSystemError::EmptyAxiom);
+        }
+
+        validate_modules(&self.arena, &axiom)?;
+        self.axiom = Some(axiom);
+
+        Ok(())
+    }
+
+    /// Register a production rule that rewrites every module with token `predecessor`.  The
+    /// `production` is called with the parameters of the module, and returns its successor.
+    ///
+    /// Rules for the same token are tried in the order they were added, and the first rule
+    /// that applies is used.  Modules that no rule applies to are left unchanged.
+    ///
+    /// This function will return an [`LSystemError::InvalidArenaId`] error if `predecessor`
+    /// is invalid.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::LSystemError;
+    /// # fn main() -> Result<(), LSystemError> {
+    /// use dcc_lsystem::parametric::{Module, ParametricLSystemBuilder};
+    ///
+    /// let mut builder = ParametricLSystemBuilder::new();
+    /// let a = builder.token("A")?;
+    /// builder.axiom(vec![Module::new(a, vec![1.0])])?;
+    ///
+    /// // A(x) => A(x+1)
+    /// builder.rule(a, move |params| vec![Module::new(a, vec![params[0] + 1.0])])?;
+    ///
+    /// let mut system = builder.finish()?;
+    /// system.step_by(3)?;
+    /// assert_eq!(system.render(), "A(4)");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn rule<P>(&mut self, predecessor: ArenaId, production: P) -> Result<(), LSystemError>
+    where
+        P: Fn(&[f64]) -> Vec<Module> + Send + Sync + 'static,
+    {
+        self.push_rule(predecessor, None, Arc::new(production))
+    }
+
+    /// Register a production rule that only rewrites modules with token `predecessor` whose
+    /// parameters satisfy `condition`.  See [`ParametricLSystemBuilder::rule()`] for details.
+    pub fn conditional_rule<C, P>(
+        &mut self,
+        predecessor: ArenaId,
+        condition: C,
+        production: P,
+    ) -> Result<(), LSystemError>
+    where
+        C: Fn(&[f64]) -> bool + Send + Sync + 'static,
+        P: Fn(&[f64]) -> Vec<Module> + Send + Sync + 'static,
+    {
+        self.push_rule(predecessor, Some(Arc::new(condition)), Arc::new(production))
+    }
+
+    fn push_rule(
+        &mut self,
+        predecessor: ArenaId,
+        condition: Option<Condition>,
+        production: Production,
+    ) -> Result<(), LSystemError> {
+        if !self.arena.is_valid(predecessor) {
+            return Err(LSystemError::InvalidArenaId(predecessor));
+        }
+
+        self.rules.push(ParametricRule {
+            predecessor,
+            condition,
+            production,
+        });
+
+        Ok(())
+    }
+
+    /// Consumes the builder, returning a [`ParametricLSystem`].  If an axiom has not been
+    /// set then this function will return an [`LSystemError::MissingAxiom`] error.
+    pub fn finish(self) -> Result<ParametricLSystem, LSystemError> {
+        let axiom = self.axiom.ok_or(LSystemError::MissingAxiom)?;
+
+        let mut rules = vec![Vec::new(); self.arena.len()];
+        for rule in self.rules.into_iter() {
+            rules[rule.predecessor.0].push(rule);
+        }
+
+        Ok(ParametricLSystem {
+            arena: self.arena,
+            state: axiom.clone(),
+            axiom,
+            rules,
+            steps: 0,
+        })
+    }
+}
+
+/// Returns an [`LSystemError::InvalidArenaId`] error if any of `modules` refer to a token that
+/// doesn't belong to `arena`.
+fn validate_modules(arena: &Arena<Token>, modules: &[Module]) -> Result<(), LSystemError> {
+    match modules.iter().find(|module| !arena.is_valid(module.id)) {
+        Some(module) => Err(LSystemError::InvalidArenaId(module.id)),
+        None => Ok(()),
+    }
+}
+
+/// A parametric L-system.  See the [module level documentation](self) for details.
+#[derive(Debug, Clone)]
+pub struct ParametricLSystem {
+    arena: Arena<Token>,
+    axiom: Vec<Module>,
+
+    // The rules for each token, indexed by ArenaId
+    rules: Vec<Vec<ParametricRule>>,
+    state: Vec<Module>,
+    steps: usize,
+}
+
+impl ParametricLSystem {
+    /// Reset the system to its axiom.
+    pub fn reset(&mut self) {
+        self.state.clone_from(&self.axiom);
+        self.steps = 0;
+    }
+
+    /// Iterate the system a single step.
+    ///
+    /// Returns an [`LSystemError::InvalidArenaId`] error (and leaves the system untouched) if a
+    /// production returns a module whose token doesn't belong to this system.
+    pub fn step(&mut self) -> Result<(), LSystemError> {
+        let mut next_state = Vec::with_capacity(self.state.len());
+
+        for module in self.state.iter() {
+            let rule = self.rules[module.id.0]
+                .iter()
+                .find(|rule| rule.applies(&module.params));
+
+            match rule {
+                Some(rule) => {
+                    let successor = (rule.production)(&module.params);
+                    validate_modules(&self.arena, &successor)?;
+                    next_state.extend(successor);
+                }
+                None => next_state.push(module.clone()),
+            }
+        }
+
+        self.state = next_state;
+        self.steps += 1;
+
+        Ok(())
+    }
+
+    /// Iterate the system by `n` steps, stopping at the first error.  See
+    /// [`ParametricLSystem::step()`].
+    pub fn step_by(&mut self, n: usize) -> Result<(), LSystemError> {
+        for _ in 0..n {
+            self.step()?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the number of iterations the system has undergone so far.
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    /// Returns the current state of the system.
+    pub fn get_state(&self) -> &[Module] {
+        &self.state
+    }
+
+    /// Returns the current state of the system as a [`String`].  Modules with parameters are
+    /// rendered as `name(p1,p2,...)`, and modules without parameters as just their name.
+    pub fn render(&self) -> String {
+        let mut rendered = String::new();
+
+        for module in self.state.iter() {
+            // unwrap: every module is validated before it enters the state
+            rendered.push_str(self.arena.get(module.id).unwrap().name());
+
+            if !module.params.is_empty() {
+                let params: Vec<String> = module.params.iter().map(f64::to_string).collect();
+
+                rendered.push('(');
+                rendered.push_str(&params.join(","));
+                rendered.push(')');
+            }
+        }
+
+        rendered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parametric_rule_order_and_errors() -> Result<(), LSystemError> {
+        let mut builder = ParametricLSystemBuilder::new();
+        let a = builder.token("A")?;
+        let b = builder.token("B")?;
+        builder.axiom(vec![Module::new(a, vec![0.0]), Module::new(b, vec![])])?;
+
+        // The first matching rule wins
+        builder.conditional_rule(
+            a,
+            |params| params[0] < 2.0,
+            move |params| vec![Module::new(a, vec![params[0] + 1.0])],
+        )?;
+        builder.rule(a, move |_| vec![Module::new(b, vec![])])?;
+
+        let mut system = builder.clone().finish()?;
+        system.step_by(2)?;
+        assert_eq!(system.render(), "A(2)B");
+        system.step()?;
+        assert_eq!(system.render(), "BB");
+
+        // Productions can't produce tokens from another system
+        builder.rule(b, |_| vec![Module::new(ArenaId(7), vec![])])?;
+        let mut system = builder.finish()?;
+        assert!(matches!(
+            system.step(),
+            Err(LSystemError::InvalidArenaId(ArenaId(7)))
+        ));
+        assert_eq!(system.steps(), 0);
+
+        Ok(())
+    }
+}
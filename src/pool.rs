@@ -0,0 +1,115 @@
+//! A pool of reusable state buffers.
+//!
+//! Every step of an [`LSystem`](crate::LSystem) produces a new state buffer and throws the old
+//! one away.  For services that step (and build) many systems in quick succession, the resulting
+//! allocator traffic can add up.  A [`BufferPool`] keeps discarded buffers around so that later
+//! steps - of the same system, or of any other system sharing the pool - can reuse their memory.
+//!
+//! # Example
+//! ```rust
+//! # use dcc_lsystem::LSystemError;
+//! # fn main() -> Result<(), LSystemError> {
+//! use dcc_lsystem::LSystemBuilder;
+//! use dcc_lsystem::pool::BufferPool;
+//!
+//! let pool = BufferPool::new();
+//!
+//! for _ in 0..10 {
+//!     let mut builder = LSystemBuilder::new();
+//!     let a = builder.token("a")?;
+//!     builder.axiom(vec![a])?;
+//!     builder.transformation_rule(a, vec![a, a])?;
+//!     builder.buffer_pool(pool.clone());
+//!
+//!     let mut system = builder.finish()?;
+//!     system.step_by(10);
+//!
+//!     // When `system` is dropped, its state buffer goes back into the pool
+//! }
+//!
+//! assert!(!pool.is_empty());
+//! # Ok(())
+//! # }
+//! ```
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::arena::ArenaId;
+
+/// A thread-safe pool of state buffers, shareable between any number of systems.
+///
+/// Cloning a [`BufferPool`] is cheap and produces a handle to the same underlying pool.
+#[derive(Clone, Debug, Default)]
+pub struct BufferPool {
+    buffers: Arc<Mutex<Vec<Vec<ArenaId>>>>,
+}
+
+impl BufferPool {
+    /// Create a new, empty, buffer pool.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<Vec<ArenaId>>> {
+        // The pool only ever holds plain buffers, so a panic while the lock was held
+        // can't have left it in an inconsistent state.
+        self.buffers
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Takes an empty buffer from the pool, or allocates a new one if the pool is empty.
+    /// The buffer with the largest capacity is handed out first.
+    ///
+    /// # Example
+    /// ```rust
+    /// use dcc_lsystem::ArenaId;
+    /// use dcc_lsystem::pool::BufferPool;
+    ///
+    /// let pool = BufferPool::new();
+    /// pool.give(Vec::with_capacity(16));
+    /// pool.give(vec![ArenaId(0); 100]);
+    ///
+    /// let buffer = pool.take();
+    /// assert!(buffer.is_empty());
+    /// assert!(buffer.capacity() >= 100);
+    /// ```
+    pub fn take(&self) -> Vec<ArenaId> {
+        let mut buffers = self.lock();
+
+        let largest = buffers
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, buffer)| buffer.capacity())
+            .map(|(index, _)| index);
+
+        largest
+            .map(|index| buffers.swap_remove(index))
+            .unwrap_or_default()
+    }
+
+    /// Returns a buffer to the pool.  The contents of the buffer are discarded, but its
+    /// memory is kept for reuse.  Buffers that never allocated are simply dropped.
+    pub fn give(&self, mut buffer: Vec<ArenaId>) {
+        if buffer.capacity() == 0 {
+            return;
+        }
+
+        buffer.clear();
+        self.lock().push(buffer);
+    }
+
+    /// Returns the number of buffers currently held by the pool.
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// Returns `true` if the pool holds no buffers.
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    /// Frees every buffer held by the pool.
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+}
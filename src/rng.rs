@@ -0,0 +1,110 @@
+//! A small, dependency-free pseudo-random number generator.
+//!
+//! The rewriting engine only needs a handful of random choices (which occurrence to rewrite,
+//! which successor to pick), so rather than pulling in a full RNG crate we use a
+//! [SplitMix64](https://prng.di.unimi.it/splitmix64.c) generator.  It is fast, has a
+//! 64-bit state, and - most importantly for us - is completely deterministic for a given seed.
+
+/// A seedable SplitMix64 pseudo-random number generator.
+///
+/// # Example
+/// ```rust
+/// use dcc_lsystem::rng::Rng;
+///
+/// let mut first = Rng::new(42);
+/// let mut second = Rng::new(42);
+///
+/// // Generators constructed from the same seed produce the same stream
+/// assert_eq!(first.next_u64(), second.next_u64());
+/// ```
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    /// Create a new generator from the given seed.
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    /// Create a generator for the `stream`-th independent stream of the given seed.
+    ///
+    /// An [`LSystem`](crate::LSystem) uses this to give each generation its own stream,
+    /// so that the random choices made while producing a generation depend only on the seed
+    /// and the generation index, and not on how the system arrived at that generation.
+    ///
+    /// # Example
+    /// ```rust
+    /// use dcc_lsystem::rng::Rng;
+    ///
+    /// let mut a = Rng::derive(42, 7);
+    /// let mut b = Rng::derive(42, 7);
+    /// let mut c = Rng::derive(42, 8);
+    ///
+    /// let x = a.next_u64();
+    /// assert_eq!(x, b.next_u64());
+    /// assert_ne!(x, c.next_u64());
+    /// ```
+    pub fn derive(seed: u64, stream: u64) -> Self {
+        // Scramble the stream index before combining it with the seed, so that nearby
+        // (seed, stream) pairs don't produce overlapping sequences.
+        let mut mixer = Self::new(stream);
+        Self::new(seed ^ mixer.next_u64())
+    }
+
+    /// Returns the next value in the stream.
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a value chosen uniformly from `0..n`.
+    ///
+    /// # Panics
+    /// Panics if `n` is zero.
+    ///
+    /// # Example
+    /// ```rust
+    /// use dcc_lsystem::rng::Rng;
+    ///
+    /// let mut rng = Rng::new(7);
+    ///
+    /// for _ in 0..100 {
+    ///     assert!(rng.gen_index(3) < 3);
+    /// }
+    /// ```
+    pub fn gen_index(&mut self, n: usize) -> usize {
+        assert!(n > 0, "cannot choose an index from an empty range");
+
+        // The multiply-shift reduction avoids the bias that a plain modulo would introduce
+        // for large values of `n`.
+        ((u128::from(self.next_u64()) * n as u128) >> 64) as usize
+    }
+
+    /// Returns a value chosen uniformly from the half-open interval `[0, 1)`.
+    ///
+    /// # Example
+    /// ```rust
+    /// use dcc_lsystem::rng::Rng;
+    ///
+    /// let mut rng = Rng::new(11);
+    /// let x = rng.gen_f64();
+    ///
+    /// assert!((0.0..1.0).contains(&x));
+    /// ```
+    pub fn gen_f64(&mut self) -> f64 {
+        // Use the top 53 bits, which is exactly the precision of an f64 mantissa.
+        (self.next_u64() >> 11) as f64 * (1.0 / (1u64 << 53) as f64)
+    }
+}
+
+impl Default for Rng {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
@@ -0,0 +1,200 @@
+//! Chunked storage for very large states.
+//!
+//! A [`StateRope`] stores a sequence of tokens as a list of fixed-size chunks rather than as one
+//! contiguous buffer.  Growing a rope never moves tokens that have already been written, so
+//! building a huge generation doesn't produce the reallocation and copy spikes of a single
+//! `Vec`.  The chunks can also be handed out independently (e.g. to worker threads), and dropping
+//! the tail of a rope frees whole chunks at a time.
+//!
+//! Ropes are produced by [`LSystem::expanded_rope()`](crate::LSystem::expanded_rope).
+use crate::arena::ArenaId;
+use crate::system::SymbolSource;
+
+/// A sequence of tokens stored as a list of fixed-size chunks.
+///
+/// # Example
+/// ```rust
+/// use dcc_lsystem::ArenaId;
+/// use dcc_lsystem::rope::StateRope;
+///
+/// let mut rope = StateRope::new(2);
+/// rope.extend_from_slice(&[ArenaId(0), ArenaId(1), ArenaId(2)]);
+///
+/// assert_eq!(rope.len(), 3);
+/// assert_eq!(rope.chunks().count(), 2);
+/// assert_eq!(rope.get(2), Some(ArenaId(2)));
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StateRope {
+    chunk_len: usize,
+    chunks: Vec<Vec<ArenaId>>,
+    len: usize,
+}
+
+impl StateRope {
+    /// Create an empty rope whose chunks hold `chunk_len` tokens each.
+    ///
+    /// # Panics
+    /// Panics if `chunk_len` is zero.
+    pub fn new(chunk_len: usize) -> Self {
+        assert!(chunk_len > 0, "rope chunks must hold at least one token");
+
+        Self {
+            chunk_len,
+            chunks: Vec::new(),
+            len: 0,
+        }
+    }
+
+    /// Create a rope containing a copy of `tokens`.
+    ///
+    /// # Panics
+    /// Panics if `chunk_len` is zero.
+    pub fn from_slice(tokens: &[ArenaId], chunk_len: usize) -> Self {
+        let mut rope = Self::new(chunk_len);
+        rope.extend_from_slice(tokens);
+        rope
+    }
+
+    /// Returns the number of tokens each chunk of this rope holds.
+    pub fn chunk_len(&self) -> usize {
+        self.chunk_len
+    }
+
+    /// Returns the number of tokens in this rope.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if this rope contains no tokens.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the token at position `index`, if there is one.
+    pub fn get(&self, index: usize) -> Option<ArenaId> {
+        self.chunks
+            .get(index / self.chunk_len)
+            .and_then(|chunk| chunk.get(index % self.chunk_len))
+            .copied()
+    }
+
+    /// Appends a token to the end of this rope.
+    pub fn push(&mut self, id: ArenaId) {
+        match self.chunks.last_mut() {
+            Some(chunk) if chunk.len() < self.chunk_len => chunk.push(id),
+            _ => {
+                let mut chunk = Vec::with_capacity(self.chunk_len);
+                chunk.push(id);
+                self.chunks.push(chunk);
+            }
+        }
+
+        self.len += 1;
+    }
+
+    /// Appends every token of `tokens` to the end of this rope.
+    pub fn extend_from_slice(&mut self, mut tokens: &[ArenaId]) {
+        while !tokens.is_empty() {
+            let space = match self.chunks.last() {
+                Some(chunk) if chunk.len() < self.chunk_len => self.chunk_len - chunk.len(),
+                _ => {
+                    self.chunks.push(Vec::with_capacity(self.chunk_len));
+                    self.chunk_len
+                }
+            };
+
+            let (head, tail) = tokens.split_at(space.min(tokens.len()));
+
+            // unwrap: we made sure there's a chunk with space above
+            self.chunks.last_mut().unwrap().extend_from_slice(head);
+            self.len += head.len();
+            tokens = tail;
+        }
+    }
+
+    /// Shortens the rope to `len` tokens, freeing any chunks that are no longer needed.
+    /// Has no effect if the rope is already shorter than `len`.
+    ///
+    /// # Example
+    /// ```rust
+    /// use dcc_lsystem::ArenaId;
+    /// use dcc_lsystem::rope::StateRope;
+    ///
+    /// let mut rope = StateRope::from_slice(&[ArenaId(0); 10], 4);
+    /// rope.truncate(5);
+    ///
+    /// assert_eq!(rope.len(), 5);
+    /// assert_eq!(rope.chunks().count(), 2);
+    /// ```
+    pub fn truncate(&mut self, len: usize) {
+        if len >= self.len {
+            return;
+        }
+
+        self.chunks.truncate(len.div_ceil(self.chunk_len));
+
+        if let Some(chunk) = self.chunks.last_mut() {
+            chunk.truncate(len - (len - 1) / self.chunk_len * self.chunk_len);
+        }
+
+        self.len = len;
+    }
+
+    /// Returns an iterator over the chunks of this rope.  Every chunk except possibly the
+    /// last holds exactly [`StateRope::chunk_len()`] tokens.
+    pub fn chunks(&self) -> impl Iterator<Item = &[ArenaId]> + '_ {
+        self.chunks.iter().map(Vec::as_slice)
+    }
+
+    /// Returns an iterator over the tokens of this rope.
+    pub fn iter(&self) -> impl Iterator<Item = ArenaId> + Clone + '_ {
+        self.chunks.iter().flatten().copied()
+    }
+
+    /// Copies the contents of this rope into a single contiguous `Vec`.
+    pub fn to_vec(&self) -> Vec<ArenaId> {
+        let mut tokens = Vec::with_capacity(self.len);
+
+        for chunk in self.chunks() {
+            tokens.extend_from_slice(chunk);
+        }
+
+        tokens
+    }
+}
+
+impl SymbolSource for StateRope {
+    fn symbols(&self) -> impl Iterator<Item = ArenaId> + '_ {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rope_push_and_truncate() {
+        let tokens: Vec<ArenaId> = (0..23).map(ArenaId).collect();
+
+        let mut pushed = StateRope::new(5);
+        for &id in tokens.iter() {
+            pushed.push(id);
+        }
+
+        let extended = StateRope::from_slice(&tokens, 5);
+        assert_eq!(pushed, extended);
+        assert_eq!(pushed.to_vec(), tokens);
+        assert!(pushed.chunks().all(|chunk| chunk.len() <= 5));
+
+        for len in (0..=23).rev() {
+            pushed.truncate(len);
+            assert_eq!(pushed.len(), len);
+            assert_eq!(pushed.to_vec(), &tokens[..len]);
+        }
+
+        assert!(pushed.is_empty());
+        assert_eq!(pushed.get(0), None);
+    }
+}
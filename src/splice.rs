@@ -0,0 +1,230 @@
+use std::ops::Range;
+
+use crate::arena::ArenaId;
+use crate::errors::LSystemError;
+
+/// Where the expansion of a token some number of generations deep can be copied from.
+#[derive(Clone, Debug)]
+enum Found {
+    /// A range of the old generation.
+    Old(Range<usize>),
+
+    /// A range of the new generation, as written so far.
+    New(Range<usize>),
+}
+
+/// Re-derives a generation of a D0L-system after the successor of a single token has
+/// changed, without re-deriving the parts of it that don't depend on that token.
+///
+/// Think of the old generation as the leaves of the derivation tree grown from an earlier
+/// generation.  A subtree in which the changed token never appears (above its leaves) comes out
+/// exactly the same under the new rule, so its leaves are copied out of the old generation.
+/// Only the subtrees containing the changed token are regrown, and even there any subtree that
+/// has been seen before (untouched in the old generation, or anywhere in the new one) is
+/// copied rather than expanded.
+pub(crate) struct Splice<'a> {
+    old: &'a [ArenaId],
+    old_successors: Vec<&'a [ArenaId]>,
+    new_successors: Vec<&'a [ArenaId]>,
+
+    // For each depth `k`, and each token (indexed by ArenaId), the length of the old
+    // expansion of the token `k` generations deep, saturating at `usize::MAX`
+    old_len: Vec<Vec<usize>>,
+
+    // For each depth `k`, and each token, whether the changed token appears in the first `k`
+    // generations (counting the token itself as the first) grown from the token
+    touched: Vec<Vec<bool>>,
+
+    // For each depth `k`, and each token, where its new expansion `k` generations deep can be
+    // copied from, once it has been found
+    found: Vec<Vec<Option<Found>>>,
+}
+
+impl<'a> Splice<'a> {
+    /// Prepare to re-derive `old`, which was grown `depth` generations from `base` using
+    /// `old_successors`, with the successor of `id` changed to the one in `new_successors`.
+    /// The successors of every other token must be the same in both.
+    pub(crate) fn new(
+        id: ArenaId,
+        base: &[ArenaId],
+        old: &'a [ArenaId],
+        depth: usize,
+        old_successors: Vec<&'a [ArenaId]>,
+        new_successors: Vec<&'a [ArenaId]>,
+    ) -> Self {
+        let alphabet = old_successors.len();
+        let mut old_len = vec![vec![1; alphabet]];
+        let mut touched = vec![vec![false; alphabet]];
+
+        for k in 1..=depth {
+            let (previous_len, previous_touched) = (&old_len[k - 1], &touched[k - 1]);
+
+            let len = old_successors
+                .iter()
+                .map(|successor| {
+                    successor
+                        .iter()
+                        .map(|child| previous_len[child.0])
+                        .fold(0, usize::saturating_add)
+                })
+                .collect();
+
+            let reaches = old_successors
+                .iter()
+                .enumerate()
+                .map(|(index, successor)| {
+                    index == id.0 || successor.iter().any(|child| previous_touched[child.0])
+                })
+                .collect();
+
+            old_len.push(len);
+            touched.push(reaches);
+        }
+
+        let mut splice = Self {
+            old,
+            old_successors,
+            new_successors,
+            old_len,
+            touched,
+            found: vec![vec![None; alphabet]; depth + 1],
+        };
+
+        let mut offset = 0;
+        for &token in base {
+            splice.survey(token, depth, offset);
+            offset = offset.saturating_add(splice.old_len[depth][token.0]);
+        }
+
+        debug_assert_eq!(offset, old.len());
+        splice
+    }
+
+    /// Returns the new generation grown `depth` generations from `base`, written into
+    /// `buffer`.  Returns an [`LSystemError::AllocationFailed`] error if it can't be
+    /// allocated.
+    pub(crate) fn derive(
+        mut self,
+        base: &[ArenaId],
+        depth: usize,
+        mut buffer: Vec<ArenaId>,
+    ) -> Result<Vec<ArenaId>, LSystemError> {
+        buffer.try_reserve(self.new_len(base, depth))?;
+
+        for &token in base {
+            self.emit(token, depth, &mut buffer);
+        }
+
+        Ok(buffer)
+    }
+
+    /// Records where the untouched subtrees below `token`, whose old expansion `depth`
+    /// generations deep starts at `offset` of the old generation, can be found.
+    fn survey(&mut self, token: ArenaId, depth: usize, offset: usize) {
+        if !self.touched[depth][token.0] {
+            let end = offset + self.old_len[depth][token.0];
+            self.found[depth][token.0].get_or_insert(Found::Old(offset..end));
+            return;
+        }
+
+        // A touched token is always at least one generation above the leaves
+        let mut offset = offset;
+        for &child in self.old_successors[token.0] {
+            self.survey(child, depth - 1, offset);
+            offset += self.old_len[depth - 1][child.0];
+        }
+    }
+
+    /// Writes the new expansion of `token`, `depth` generations deep, to `buffer`.
+    fn emit(&mut self, token: ArenaId, depth: usize, buffer: &mut Vec<ArenaId>) {
+        // Only untouched subtrees are found in the old generation, but every subtree has the
+        // same expansion wherever it appears in the new one
+        match &self.found[depth][token.0] {
+            Some(Found::Old(range)) => buffer.extend_from_slice(&self.old[range.clone()]),
+            Some(Found::New(range)) => buffer.extend_from_within(range.clone()),
+            None => {
+                let start = buffer.len();
+
+                if depth == 0 {
+                    buffer.push(token);
+                } else {
+                    for &child in self.new_successors[token.0] {
+                        self.emit(child, depth - 1, buffer);
+                    }
+                }
+
+                self.found[depth][token.0] = Some(Found::New(start..buffer.len()));
+            }
+        }
+    }
+
+    /// Returns the length of the new generation grown `depth` generations from `base`,
+    /// saturating at `usize::MAX`.
+    fn new_len(&self, base: &[ArenaId], depth: usize) -> usize {
+        let mut len = vec![1usize; self.new_successors.len()];
+
+        for _ in 0..depth {
+            len = self
+                .new_successors
+                .iter()
+                .map(|successor| {
+                    successor
+                        .iter()
+                        .map(|child| len[child.0])
+                        .fold(0, usize::saturating_add)
+                })
+                .collect();
+        }
+
+        base.iter()
+            .map(|token| len[token.0])
+            .fold(0, usize::saturating_add)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Grows `base` for `depth` generations the slow way.
+    fn grow(base: &[ArenaId], depth: usize, successors: &[&[ArenaId]]) -> Vec<ArenaId> {
+        (0..depth).fold(base.to_vec(), |state, _| {
+            state
+                .iter()
+                .flat_map(|id| successors[id.0].iter().copied())
+                .collect()
+        })
+    }
+
+    #[test]
+    fn test_splice_matches_regrowing() -> Result<(), LSystemError> {
+        let (x, f, plus) = (ArenaId(0), ArenaId(1), ArenaId(2));
+        let rules = [vec![f, plus, x, f], vec![f, f], vec![plus]];
+        let old_successors: Vec<&[ArenaId]> = rules.iter().map(Vec::as_slice).collect();
+        let base = [x, plus, f];
+
+        for new_successor in [&[x, x][..], &[], &[f], &[plus, x, plus]] {
+            let mut new_successors = old_successors.clone();
+            new_successors[x.0] = new_successor;
+
+            for depth in 0..6 {
+                let old = grow(&base, depth, &old_successors);
+                let splice = Splice::new(
+                    x,
+                    &base,
+                    &old,
+                    depth,
+                    old_successors.clone(),
+                    new_successors.clone(),
+                );
+
+                assert_eq!(
+                    splice.derive(&base, depth, Vec::new())?,
+                    grow(&base, depth, &new_successors)
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
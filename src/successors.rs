@@ -0,0 +1,263 @@
+use crate::arena::ArenaId;
+
+/// One of the possible successors of a token, stored as a span of [`SuccessorTable::tokens`].
+#[derive(Copy, Clone, Debug)]
+struct Successor {
+    offset: usize,
+    len: usize,
+    weight: f64,
+
+    // The sum of the weights of this successor and all the alternatives before it, relative
+    // to the largest weight of the alternatives
+    cumulative_weight: f64,
+}
+
+/// A successor that only applies when the token is surrounded by the given contexts.
+#[derive(Clone, Debug)]
+struct ContextSuccessor {
+    left: Vec<ArenaId>,
+    right: Vec<ArenaId>,
+    successor: Vec<ArenaId>,
+}
+
+/// The successors of every token of a system, stored back to back in a single buffer.
+#[derive(Clone, Debug)]
+pub(crate) struct SuccessorTable {
+    tokens: Vec<ArenaId>,
+    successors: Vec<Successor>,
+
+    // For each token (indexed by ArenaId), the range of `successors` holding its alternatives
+    alternatives: Vec<(usize, usize)>,
+
+    // For each token, whether rewriting it can change it
+    rewritable: Vec<bool>,
+
+    // For each token, its context-sensitive successors in the order they were added
+    contexts: Vec<Vec<ContextSuccessor>>,
+
+    // For each token, whether it is skipped over when matching contexts
+    ignored: Vec<bool>,
+}
+
+impl SuccessorTable {
+    /// Build a table from the weighted alternatives of each token, where the alternatives of a
+    /// token are found at the index given by its [`ArenaId`].  Every token must have at least
+    /// one alternative, and all weights must be positive.
+    pub(crate) fn new(rules: Vec<Vec<(f64, Vec<ArenaId>)>>) -> Self {
+        let mut tokens = Vec::new();
+        let mut successors = Vec::new();
+        let mut alternatives = Vec::with_capacity(rules.len());
+        let mut rewritable = Vec::with_capacity(rules.len());
+        let contexts = vec![Vec::new(); rules.len()];
+        let ignored = vec![false; rules.len()];
+
+        for (index, choices) in rules.into_iter().enumerate() {
+            debug_assert!(!choices.is_empty());
+
+            let start = successors.len();
+            let mut cumulative_weight = 0.0;
+
+            // Scaling by the largest weight keeps the running total finite, however large the
+            // weights are
+            let scale = choices
+                .iter()
+                .map(|&(weight, _)| weight)
+                .fold(0.0, f64::max);
+
+            rewritable.push(
+                choices
+                    .iter()
+                    .any(|(_, successor)| successor.as_slice() != [ArenaId(index)]),
+            );
+
+            for (weight, successor) in choices {
+                cumulative_weight += weight / scale;
+
+                successors.push(Successor {
+                    offset: tokens.len(),
+                    len: successor.len(),
+                    weight,
+                    cumulative_weight,
+                });
+
+                tokens.extend(successor);
+            }
+
+            alternatives.push((start, successors.len()));
+        }
+
+        Self {
+            tokens,
+            successors,
+            alternatives,
+            rewritable,
+            contexts,
+            ignored,
+        }
+    }
+
+    /// Add a successor for `predecessor` that only applies when it is immediately preceded by
+    /// `left` and followed by `right`.  Context successors take priority over the ordinary
+    /// successors of a token, and are tried in the order they were added.
+    pub(crate) fn add_context(
+        &mut self,
+        left: Vec<ArenaId>,
+        predecessor: ArenaId,
+        right: Vec<ArenaId>,
+        successor: Vec<ArenaId>,
+    ) {
+        self.contexts[predecessor.0].push(ContextSuccessor {
+            left,
+            right,
+            successor,
+        });
+    }
+
+    /// Skip over `id` when matching contexts, so that it is invisible to context-sensitive
+    /// successors.
+    pub(crate) fn ignore(&mut self, id: ArenaId) {
+        self.ignored[id.0] = true;
+    }
+
+    /// Returns `true` if some token has a context-sensitive successor.
+    pub(crate) fn has_contexts(&self) -> bool {
+        self.contexts.iter().any(|contexts| !contexts.is_empty())
+    }
+
+    /// Returns the first context-sensitive successor of the token at `position` whose contexts
+    /// match, where `at` returns the token at a given position of the state.  Ignored tokens
+    /// are skipped over while matching.  Returns `None` if the token has no matching context
+    /// successor.
+    #[inline]
+    pub(crate) fn contextual<A>(&self, id: ArenaId, position: usize, at: &A) -> Option<&[ArenaId]>
+    where
+        A: Fn(usize) -> Option<ArenaId>,
+    {
+        self.contextual_index(id, position, at)
+            .map(|index| self.contexts[id.0][index].successor.as_slice())
+    }
+
+    /// Returns the index (among the context-sensitive successors of `id`, in the order they
+    /// were added) of the successor that [`SuccessorTable::contextual()`] would return.
+    #[inline]
+    pub(crate) fn contextual_index<A>(&self, id: ArenaId, position: usize, at: &A) -> Option<usize>
+    where
+        A: Fn(usize) -> Option<ArenaId>,
+    {
+        self.contexts[id.0].iter().position(|context| {
+            self.matches_left(&context.left, position, at)
+                && self.matches_right(&context.right, position, at)
+        })
+    }
+
+    /// Returns `true` if the tokens before `position` that aren't ignored end with `left`.
+    fn matches_left<A>(&self, left: &[ArenaId], mut position: usize, at: &A) -> bool
+    where
+        A: Fn(usize) -> Option<ArenaId>,
+    {
+        left.iter().rev().all(|&expected| loop {
+            if position == 0 {
+                return false;
+            }
+
+            position -= 1;
+
+            match at(position) {
+                Some(id) if self.ignored[id.0] => continue,
+                found => return found == Some(expected),
+            }
+        })
+    }
+
+    /// Returns `true` if the tokens after `position` that aren't ignored start with `right`.
+    fn matches_right<A>(&self, right: &[ArenaId], mut position: usize, at: &A) -> bool
+    where
+        A: Fn(usize) -> Option<ArenaId>,
+    {
+        right.iter().all(|&expected| loop {
+            position += 1;
+
+            match at(position) {
+                Some(id) if self.ignored[id.0] => continue,
+                found => return found == Some(expected),
+            }
+        })
+    }
+
+    /// Replace the alternatives of `id`.
+    pub(crate) fn replace(&mut self, id: ArenaId, choices: Vec<(f64, Vec<ArenaId>)>) {
+        let mut rules: Vec<Vec<(f64, Vec<ArenaId>)>> = self
+            .alternatives
+            .iter()
+            .map(|&(start, end)| {
+                self.successors[start..end]
+                    .iter()
+                    .map(|successor| {
+                        let tokens =
+                            &self.tokens[successor.offset..successor.offset + successor.len];
+                        (successor.weight, tokens.to_vec())
+                    })
+                    .collect()
+            })
+            .collect();
+
+        rules[id.0] = choices;
+
+        let contexts = std::mem::take(&mut self.contexts);
+        let ignored = std::mem::take(&mut self.ignored);
+        *self = Self::new(rules);
+        self.contexts = contexts;
+        self.ignored = ignored;
+    }
+
+    /// Returns `true` if applying a context-free production rule to `id` can change it.
+    #[inline]
+    pub(crate) fn is_rewritable(&self, id: ArenaId) -> bool {
+        self.rewritable[id.0]
+    }
+
+    /// Returns the successor of `id` if it has exactly one, or `None` if the successor is
+    /// chosen at random or depends on context.
+    #[inline]
+    pub(crate) fn deterministic(&self, id: ArenaId) -> Option<&[ArenaId]> {
+        let (start, end) = self.alternatives[id.0];
+
+        if end - start == 1 && self.contexts[id.0].is_empty() {
+            let successor = &self.successors[start];
+            Some(&self.tokens[successor.offset..successor.offset + successor.len])
+        } else {
+            None
+        }
+    }
+
+    /// Returns a successor of `id`.  If `id` has more than one possible successor, `sample`
+    /// is called to obtain a value in `[0, 1)` that is used to choose between them in proportion
+    /// to their weights.
+    #[inline]
+    pub(crate) fn choose<F: FnOnce() -> f64>(&self, id: ArenaId, sample: F) -> &[ArenaId] {
+        let (start, _) = self.alternatives[id.0];
+        let successor = &self.successors[start + self.choose_index(id, sample)];
+
+        &self.tokens[successor.offset..successor.offset + successor.len]
+    }
+
+    /// Returns the index (among the alternatives of `id`) of the successor that
+    /// [`SuccessorTable::choose()`] would return for the same `sample`.
+    #[inline]
+    pub(crate) fn choose_index<F: FnOnce() -> f64>(&self, id: ArenaId, sample: F) -> usize {
+        let (start, end) = self.alternatives[id.0];
+
+        if end - start == 1 {
+            return 0;
+        }
+
+        let choices = &self.successors[start..end];
+        let target = sample() * choices[choices.len() - 1].cumulative_weight;
+
+        choices
+            .iter()
+            .position(|choice| target < choice.cumulative_weight)
+            // Guard against floating point error putting `target` right at the top end
+            .unwrap_or(choices.len() - 1)
+    }
+}
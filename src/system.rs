@@ -61,40 +61,659 @@
 //! # Ok(())
 //! # }
 //! ```
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
 
 use crate::arena::{Arena, ArenaId};
+use crate::errors::LSystemError;
+use crate::pool::BufferPool;
+use crate::rng::Rng;
+use crate::rope::StateRope;
+use crate::splice::Splice;
+use crate::successors::SuccessorTable;
 use crate::token::Token;
 
+/// The strategy an [`LSystem`] uses to apply its production rules during a single step.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum RewriteMode {
+    /// Every token in the state is rewritten simultaneously.  This is the classical
+    /// Lindenmayer system behaviour.
+    #[default]
+    Parallel,
+
+    /// Only the leftmost token with a (non-trivial) production rule is rewritten.
+    SequentialFirst,
+
+    /// A single token with a (non-trivial) production rule, chosen uniformly at random
+    /// from all such tokens in the state, is rewritten.
+    SequentialRandom,
+}
+
+/// The outcome of running an [`LSystem`] from one of the axioms given to
+/// [`LSystem::compare_axioms()`].
+#[derive(Clone, Debug)]
+pub struct AxiomRun {
+    /// The axiom the run started from.
+    pub axiom: Vec<ArenaId>,
+
+    /// The state of the system after the requested number of steps.
+    pub state: Vec<ArenaId>,
+
+    /// The number of times each token occurs in `state`.
+    pub counts: HashMap<ArenaId, usize>,
+}
+
+/// A saved copy of the state of an [`LSystem`], taken with [`LSystem::snapshot()`] and
+/// restored with [`LSystem::restore()`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Snapshot {
+    axiom: Vec<ArenaId>,
+    resets: u64,
+    state: Vec<ArenaId>,
+    steps: usize,
+}
+
+impl Snapshot {
+    /// Returns the generation the system was in when the snapshot was taken.
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    /// Returns the state of the system when the snapshot was taken.
+    pub fn state(&self) -> &[ArenaId] {
+        &self.state
+    }
+}
+
+/// Describes a single step taken by an [`LSystem`].  See [`LSystem::subscribe()`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct StepEvent {
+    /// The generation the system is in after the step.
+    pub generation: usize,
+
+    /// The number of tokens in the state before the step.
+    pub old_len: usize,
+
+    /// The number of tokens in the state after the step.
+    pub new_len: usize,
+
+    /// The number of tokens that were rewritten by a (non-trivial) production rule.
+    pub rules_applied: usize,
+}
+
+/// Identifies the production rule that rewrote a token.  See [`LSystem::enable_trace()`].
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub enum FiredRule {
+    /// The context-sensitive rule for the token with the given index, counting the token's
+    /// context-sensitive rules in the order they were added.
+    Context(usize),
+
+    /// The ordinary rule for the token with the given index, counting the token's rules in the
+    /// order they were added.  The alternatives of a stochastic rule are counted one by one.
+    Rule(usize),
+}
+
+/// A single application of a production rule, recorded in a [`StepTrace`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RuleApplication {
+    /// The position of the rewritten token in the state before the step.
+    pub position: usize,
+
+    /// The rewritten token.
+    pub predecessor: ArenaId,
+
+    /// The rule that rewrote the token.
+    pub rule: FiredRule,
+
+    /// The tokens the token was rewritten to.
+    pub successor: Vec<ArenaId>,
+}
+
+/// The production rules applied by a single step of an [`LSystem`].  See
+/// [`LSystem::enable_trace()`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StepTrace {
+    /// The generation the system is in after the step.
+    pub generation: usize,
+
+    /// The name of the rule table used for the step, or `None` for the default rules.
+    pub table: Option<String>,
+
+    /// The rules applied by the step, in order of position.  Tokens that were left as they
+    /// were (including those rewritten by a rule `P => P`) are left out.
+    pub applications: Vec<RuleApplication>,
+}
+
+impl StepTrace {
+    /// Returns the number of times each rule was applied during the step, keyed by the
+    /// rewritten token and the rule.
+    pub fn counts(&self) -> HashMap<(ArenaId, FiredRule), usize> {
+        let mut counts = HashMap::new();
+
+        for application in &self.applications {
+            *counts
+                .entry((application.predecessor, application.rule))
+                .or_insert(0) += 1;
+        }
+
+        counts
+    }
+}
+
+/// An iterator over the tokens of a generation of an [`LSystem`].  See
+/// [`LSystem::symbols_at_depth()`].
+#[derive(Clone, Debug)]
+pub struct SymbolsAtDepth<'a> {
+    inner: DepthInner<'a>,
+}
+
+#[derive(Clone, Debug)]
+enum DepthInner<'a> {
+    /// A depth first expansion of the axiom.
+    Lazy {
+        system: &'a LSystem,
+        depth: usize,
+
+        // The tokens still to be expanded at each level of the expansion, along with the
+        // generation they belong to
+        stack: Vec<(usize, &'a [ArenaId])>,
+
+        // The position within each generation of the next token to be expanded
+        positions: Vec<usize>,
+    },
+
+    /// A generation that had to be derived in full.
+    Expanded(std::vec::IntoIter<ArenaId>),
+}
+
+/// The names of every token of a system, copied out of the arena so that rendering a state
+/// doesn't have to go through [`Arena::get()`] for every token.
+#[derive(Clone, Debug)]
+struct NameTable {
+    // The name of each token, indexed by ArenaId
+    names: Vec<Box<str>>,
+
+    // A hash of the name of each token (less than `MODULUS`), used to fingerprint states
+    hashes: Vec<u64>,
+
+    // If every name is a single ASCII character, the character of each token
+    ascii: Option<Vec<u8>>,
+}
+
+impl NameTable {
+    fn new(arena: &Arena<Token>) -> Self {
+        let names = arena.iter().map(|token| token.name().into()).collect();
+        let hashes = arena
+            .iter()
+            .map(|token| fnv1a(token.name().as_bytes()) % MODULUS)
+            .collect();
+
+        let ascii = arena
+            .iter()
+            .map(|token| match token.name().as_bytes() {
+                &[byte] if byte.is_ascii() => Some(byte),
+                _ => None,
+            })
+            .collect();
+
+        Self {
+            names,
+            hashes,
+            ascii,
+        }
+    }
+
+    #[inline]
+    fn get(&self, id: ArenaId) -> &str {
+        &self.names[id.0]
+    }
+
+    /// Returns a hash of the names of `tokens`, in order.
+    fn fingerprint(&self, tokens: &[ArenaId]) -> u64 {
+        let mut fingerprint = Fingerprint::default();
+        fingerprint.extend(self, tokens);
+        fingerprint.finish()
+    }
+
+    /// Returns the names of `tokens` concatenated into a single string.
+    fn render<I>(&self, tokens: I) -> String
+    where
+        I: Iterator<Item = ArenaId> + Clone,
+    {
+        if let Some(ascii) = self.ascii.as_ref() {
+            return tokens.map(|id| ascii[id.0] as char).collect();
+        }
+
+        // Work out the final length first, so that we only allocate once.
+        let len = tokens.clone().map(|id| self.names[id.0].len()).sum();
+        let mut rendered = String::with_capacity(len);
+
+        for id in tokens {
+            rendered.push_str(&self.names[id.0]);
+        }
+
+        rendered
+    }
+}
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+// Fingerprints are polynomial hashes modulo the Mersenne prime 2^61 - 1.  (Modulo 2^64, the
+// Thue-Morse sequence, itself an L-system, collides with its complement whatever the base.)
+const MODULUS: u64 = (1 << 61) - 1;
+const BASE: u64 = 0x0d9e_8f7a_6b5c_4d3f;
+
+/// The fingerprint of a sequence of tokens, built up token by token.  Fingerprints of
+/// consecutive pieces of a sequence can be joined together, so that the pieces can be hashed
+/// separately (on different threads, say).
+#[derive(Clone, Copy, Debug, Default)]
+struct Fingerprint {
+    // `h[0] * BASE^(len - 1) + ... + h[len - 1]`, modulo `MODULUS`, where `h[i]` is the hash
+    // of the name of the `i`-th token
+    hash: u64,
+    len: usize,
+}
+
+impl Fingerprint {
+    /// Appends `tokens` to the sequence.
+    #[inline]
+    fn extend(&mut self, names: &NameTable, tokens: &[ArenaId]) {
+        for id in tokens {
+            self.hash = add_mod(mul_mod(self.hash, BASE), names.hashes[id.0]);
+        }
+
+        self.len += tokens.len();
+    }
+
+    /// Appends the sequence fingerprinted by `other`.
+    fn join(&mut self, other: Fingerprint) {
+        self.hash = add_mod(mul_mod(self.hash, pow_mod(BASE, other.len)), other.hash);
+        self.len += other.len;
+    }
+
+    /// Returns the fingerprint as a single number.
+    fn finish(self) -> u64 {
+        // Mix in the length, and spread the bits of the result
+        let mut hash = (self.hash ^ self.len as u64).wrapping_mul(FNV_PRIME);
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+        hash ^ (hash >> 33)
+    }
+}
+
+/// Returns `a + b` modulo `MODULUS`, for `a` and `b` less than `MODULUS`.
+#[inline]
+fn add_mod(a: u64, b: u64) -> u64 {
+    let sum = a + b;
+
+    if sum >= MODULUS {
+        sum - MODULUS
+    } else {
+        sum
+    }
+}
+
+/// Returns `a * b` modulo `MODULUS`, for `a` and `b` less than `MODULUS`.
+#[inline]
+fn mul_mod(a: u64, b: u64) -> u64 {
+    let product = a as u128 * b as u128;
+
+    // 2^61 is 1 modulo `MODULUS`, so the high bits can be added onto the low ones
+    add_mod((product >> 61) as u64, product as u64 & MODULUS)
+}
+
+/// Returns `base^exponent` modulo `MODULUS`, for `base` less than `MODULUS`.
+fn pow_mod(mut base: u64, mut exponent: usize) -> u64 {
+    let mut power = 1;
+
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            power = mul_mod(power, base);
+        }
+
+        base = mul_mod(base, base);
+        exponent >>= 1;
+    }
+
+    power
+}
+
+/// Returns the 64-bit FNV-1a hash of `bytes`.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(FNV_OFFSET, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(FNV_PRIME)
+    })
+}
+
+/// The smallest number of tokens worth handing to a thread of its own when expanding a
+/// state with [`LSystem::set_threads()`].
+const MIN_CHUNK_LEN: usize = 1 << 14;
+
+/// A type that is told about every step taken by an [`LSystem`], along with the state the
+/// step produced.  See [`LSystem::observe()`].
+///
+/// Any `FnMut(StepEvent, &[ArenaId])` closure is a `StepObserver`.
+pub trait StepObserver: Send {
+    /// Called after every step with a description of the step, and the new state.
+    fn on_step(&mut self, event: StepEvent, state: &[ArenaId]);
+}
+
+impl<F> StepObserver for F
+where
+    F: FnMut(StepEvent, &[ArenaId]) + Send,
+{
+    fn on_step(&mut self, event: StepEvent, state: &[ArenaId]) {
+        self(event, state)
+    }
+}
+
+/// A sequence of tokens that can be read any number of times, such as the state of an
+/// [`LSystem`] or a lazily expanded generation.  Code that consumes tokens (a renderer, say)
+/// can accept any `SymbolSource` rather than requiring an [`LSystem`].
+///
+/// # Example
+/// ```rust
+/// # use dcc_lsystem::{LSystemError, LSystemBuilder};
+/// # fn main() -> Result<(), LSystemError> {
+/// # let mut builder = LSystemBuilder::new();
+/// # let a = builder.token("a")?;
+/// # let b = builder.token("b")?;
+/// # builder.axiom(vec![a])?;
+/// # builder.transformation_rule(a, vec![a, b])?;
+/// # let mut system = builder.finish()?;
+/// use dcc_lsystem::system::SymbolSource;
+///
+/// fn count<S: SymbolSource>(source: &S) -> usize {
+///     source.symbols().count()
+/// }
+///
+/// // `system` is an LSystem with axiom `a` and transformation rule `a -> ab`.
+/// system.step_by(2);
+/// assert_eq!(count(&system), 3);
+/// assert_eq!(count(&system.symbols_at_depth(5)), 6);
+/// assert_eq!(count(&system.expanded_rope(4, 2)), 5);
+/// # Ok(())
+/// # }
+/// ```
+pub trait SymbolSource {
+    /// Returns an iterator over the tokens of this source, in order.
+    fn symbols(&self) -> impl Iterator<Item = ArenaId> + '_;
+}
+
+impl SymbolSource for LSystem {
+    fn symbols(&self) -> impl Iterator<Item = ArenaId> + '_ {
+        self.state.iter().copied()
+    }
+}
+
+impl SymbolSource for [ArenaId] {
+    fn symbols(&self) -> impl Iterator<Item = ArenaId> + '_ {
+        self.iter().copied()
+    }
+}
+
+impl SymbolSource for Vec<ArenaId> {
+    fn symbols(&self) -> impl Iterator<Item = ArenaId> + '_ {
+        self.iter().copied()
+    }
+}
+
+impl SymbolSource for SymbolsAtDepth<'_> {
+    fn symbols(&self) -> impl Iterator<Item = ArenaId> + '_ {
+        self.clone()
+    }
+}
+
+type Subscriber = Arc<dyn Fn(StepEvent) + Send + Sync>;
+type Observer = Arc<Mutex<dyn StepObserver>>;
+
+/// The callbacks and observers registered on an [`LSystem`].
+#[derive(Clone, Default)]
+struct Subscribers {
+    callbacks: Vec<Subscriber>,
+    observers: Vec<Observer>,
+}
+
+impl Subscribers {
+    fn is_empty(&self) -> bool {
+        self.callbacks.is_empty() && self.observers.is_empty()
+    }
+}
+
+impl std::fmt::Debug for Subscribers {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(
+            f,
+            "{} subscriber(s), {} observer(s)",
+            self.callbacks.len(),
+            self.observers.len()
+        )
+    }
+}
+
+type AxiomGenerator = Arc<dyn Fn(&mut Rng) -> Vec<ArenaId> + Send + Sync>;
+
+/// Where an [`LSystem`] gets its axiom from.
+#[derive(Clone)]
+pub(crate) enum AxiomSource {
+    /// The same axiom every time.
+    Fixed(Vec<ArenaId>),
+
+    /// One of several axioms, chosen at random in proportion to their weights.
+    Weighted(Vec<(f64, Vec<ArenaId>)>),
+
+    /// Whatever the closure returns.
+    Generator(AxiomGenerator),
+}
+
+impl AxiomSource {
+    /// Returns an axiom from this source, using `rng` for any random choices.
+    fn choose(&self, rng: &mut Rng) -> Vec<ArenaId> {
+        match self {
+            AxiomSource::Fixed(axiom) => axiom.clone(),
+            AxiomSource::Weighted(alternatives) => {
+                // Scaling by the largest weight keeps the total finite, however large the
+                // weights are
+                let scale = alternatives
+                    .iter()
+                    .map(|&(weight, _)| weight)
+                    .fold(0.0, f64::max);
+                let total: f64 = alternatives.iter().map(|(weight, _)| weight / scale).sum();
+                let mut target = rng.gen_f64() * total;
+
+                for (weight, axiom) in alternatives.iter() {
+                    if target < weight / scale {
+                        return axiom.clone();
+                    }
+
+                    target -= weight / scale;
+                }
+
+                // Guard against floating point error putting `target` right at the top end
+                // unwrap: the builder doesn't accept an empty list of alternatives
+                alternatives.last().unwrap().1.clone()
+            }
+            AxiomSource::Generator(generator) => generator(rng),
+        }
+    }
+}
+
+impl std::fmt::Debug for AxiomSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match self {
+            AxiomSource::Fixed(axiom) => f.debug_tuple("Fixed").field(axiom).finish(),
+            AxiomSource::Weighted(alternatives) => {
+                f.debug_tuple("Weighted").field(alternatives).finish()
+            }
+            AxiomSource::Generator(_) => f.write_str("Generator"),
+        }
+    }
+}
+
+/// Returns the number of times each token occurs in `state`.
+fn count_tokens(state: &[ArenaId]) -> HashMap<ArenaId, usize> {
+    let mut counts = HashMap::new();
+
+    for &id in state.iter() {
+        *counts.entry(id).or_insert(0) += 1;
+    }
+
+    counts
+}
+
+/// Returns the number of times each token occurs in `state`, indexed by [`ArenaId`], for an
+/// alphabet of `len` tokens.
+fn alphabet_counts(state: &[ArenaId], len: usize) -> Vec<usize> {
+    let mut counts = vec![0; len];
+
+    for &id in state.iter() {
+        counts[id.0] += 1;
+    }
+
+    counts
+}
+
+/// Appends `tokens` to `buffer`, returning the buffer.
+fn extend(mut buffer: Vec<ArenaId>, tokens: &[ArenaId]) -> Result<Vec<ArenaId>, LSystemError> {
+    buffer.try_reserve(tokens.len())?;
+    buffer.extend_from_slice(tokens);
+    Ok(buffer)
+}
+
 /// Main struct for working with Lindenmayer systems.
+///
+/// An `LSystem` is `Send` and `Sync` (callbacks and observers must be thread-safe too), so a
+/// derived system can be shared between threads and read by all of them at once, for example
+/// to render it several different ways concurrently.
 #[derive(Clone, Debug)]
 pub struct LSystem {
     arena: Arena<Token>,
+    names: NameTable,
     axiom: Vec<ArenaId>,
-    rules_map: HashMap<ArenaId, Vec<ArenaId>>,
+    axioms: AxiomSource,
+    resets: u64,
+    successors: SuccessorTable,
     state: Vec<ArenaId>,
+
+    // The fingerprint of `state`, worked out the first time it is asked for, or while `state`
+    // was written if the fingerprint of the generation before it was asked for
+    fingerprint: OnceLock<u64>,
+
+    // The number of times each token occurs in `state` (indexed by ArenaId), kept while
+    // stepping a D0L-system so that the length of the next generation is known in advance
+    alphabet_counts: OnceLock<Vec<usize>>,
     steps: usize,
+    mode: RewriteMode,
+    seed: u64,
+    subscribers: Subscribers,
+    cache: Option<BTreeMap<usize, Vec<ArenaId>>>,
+    cache_budget: Option<usize>,
+
+    // The token counts of every generation up to the current one, if enabled
+    count_history: Option<Vec<HashMap<ArenaId, usize>>>,
+
+    // The rules applied by each step, if enabled
+    trace: Option<Vec<StepTrace>>,
+    capacity_hint: Option<usize>,
+    interpretations: HashMap<ArenaId, Vec<ArenaId>>,
+
+    // The named rule tables, and the generations from which each table (or the default
+    // rules, for `None`) is used
+    tables: Vec<(String, SuccessorTable)>,
+    schedule: BTreeMap<usize, Option<usize>>,
+
+    // The buffer of the previous state, kept for reuse while there is a capacity hint
+    spare: Vec<ArenaId>,
+    threads: usize,
+    memoize: bool,
+    pool: Option<BufferPool>,
 }
 
 impl LSystem {
-    /// Create a new instance of [`LSystem`].  In general you should avoid using this method directly
+    /// Create a new instance of [`LSystem`].  The `successors` must describe the production
+    /// rules of every token of `arena`.  In general you should avoid using this method directly
     /// and use a [`LSystemBuilder`](dcc_lsystem::LSystemBuilder) instead.
+    ///
+    /// Returns an error if the first axiom produced by `axioms` is empty or contains an
+    /// invalid [`ArenaId`].
     pub(crate) fn new(
         arena: Arena<Token>,
-        axiom: Vec<ArenaId>,
-        rules_map: HashMap<ArenaId, Vec<ArenaId>>,
-    ) -> Self {
-        Self {
+        axioms: AxiomSource,
+        successors: SuccessorTable,
+        mode: RewriteMode,
+        seed: u64,
+        threads: usize,
+        pool: Option<BufferPool>,
+    ) -> Result<Self, LSystemError> {
+        let mut system = Self {
+            names: NameTable::new(&arena),
             arena,
-            axiom: axiom.clone(),
-            rules_map,
-            state: axiom,
+            axiom: Vec::new(),
+            axioms,
+            resets: 0,
+            successors,
+            state: Vec::new(),
+            fingerprint: OnceLock::new(),
+            alphabet_counts: OnceLock::new(),
             steps: 0,
+            mode,
+            seed,
+            subscribers: Subscribers::default(),
+            cache: None,
+            cache_budget: None,
+            count_history: None,
+            trace: None,
+            capacity_hint: None,
+            interpretations: HashMap::new(),
+            tables: Vec::new(),
+            schedule: BTreeMap::new(),
+            spare: Vec::new(),
+            threads,
+            memoize: false,
+            pool,
+        };
+
+        system.axiom = system.choose_axiom()?;
+        system.state = system.axiom.clone();
+
+        Ok(system)
+    }
+
+    /// Returns an axiom from the axiom source of this system, checking that it's valid.
+    fn choose_axiom(&self) -> Result<Vec<ArenaId>, LSystemError> {
+        // Generations use the streams `0, 1, 2, ...`, so axioms are drawn from the very
+        // last stream (which no generation will ever reach), one draw per reset.
+        let axiom_seed = Rng::derive(self.seed, u64::MAX).next_u64();
+        let axiom = self
+            .axioms
+            .choose(&mut Rng::derive(axiom_seed, self.resets));
+
+        if axiom.is_empty() {
+            return Err(LSystemError::EmptyAxiom);
+        }
+
+        if let Some(&id) = axiom.iter().find(|&&id| !self.arena.is_valid(id)) {
+            return Err(LSystemError::InvalidArenaId(id));
         }
+
+        Ok(axiom)
     }
 
     /// Reset the system to its initial state.
     ///
+    /// If the system was built with a stochastic axiom (see
+    /// [`LSystemBuilder::stochastic_axiom()`](crate::LSystemBuilder::stochastic_axiom) and
+    /// [`LSystemBuilder::axiom_generator()`](crate::LSystemBuilder::axiom_generator)), a new
+    /// axiom is chosen.  The `n`-th reset of systems with the same seed always chooses the
+    /// same axiom.
+    ///
+    /// # Panics
+    /// Panics if an axiom generator returns an empty axiom or one with invalid ids.  Use
+    /// [`LSystem::try_reset()`] to handle this case.
+    ///
     /// # Example
     /// ```rust
     /// # use dcc_lsystem::LSystemError;
@@ -120,11 +739,15 @@ impl LSystem {
     /// # }
     /// ```
     pub fn reset(&mut self) {
-        self.state = self.axiom.clone();
-        self.steps = 0;
+        if let Err(e) = self.try_reset() {
+            panic!("{}", e);
+        }
     }
 
-    /// Iterate the system a single step.
+    /// Reset the system to its initial state, returning an [`LSystemError::EmptyAxiom`] or
+    /// [`LSystemError::InvalidArenaId`] error if an axiom generator returns an empty axiom or
+    /// one with invalid ids.  The system is left as it was on failure, although the failed
+    /// reset still counts towards choosing the next axiom.
     ///
     /// # Example
     /// ```rust
@@ -132,35 +755,44 @@ impl LSystem {
     /// # fn main() -> Result<(), LSystemError> {
     /// # let mut builder = LSystemBuilder::new();
     /// # let a = builder.token("a")?;
-    /// # let b = builder.token("b")?;
-    /// # builder.axiom(vec![a])?;
-    /// # builder.transformation_rule(a,vec![a, b, b, a])?;
+    /// # builder.axiom_generator(move |_| vec![a]);
+    /// # builder.transformation_rule(a, vec![a, a])?;
     /// # let mut system = builder.finish()?;
-    /// // `system` is an LSystem with axiom `a` and transformation rule `a -> abba`.
+    /// // `system` is an LSystem with an axiom generator and transformation rule `a -> aa`.
+    /// system.step_by(2);
+    /// system.try_reset()?;
     /// assert_eq!(system.render(), "a");
-    ///
-    /// // Iterate the system a single time
-    /// system.step();
-    /// assert_eq!(system.render(), "abba");
-    ///
-    /// // Once more for good luck
-    /// system.step();
-    /// assert_eq!(system.render(), "abbabbabba");
     /// # Ok(())
     /// # }
     /// ```
-    pub fn step(&mut self) {
-        let mut next_state = Vec::new();
+    pub fn try_reset(&mut self) -> Result<(), LSystemError> {
+        let stochastic = !matches!(self.axioms, AxiomSource::Fixed(_));
 
-        for id in self.state.iter() {
-            next_state.extend(self.rules_map[id].clone());
+        if stochastic {
+            self.resets += 1;
+            self.axiom = self.choose_axiom()?;
         }
 
-        self.state = next_state;
-        self.steps += 1;
+        self.rewind()?;
+
+        // The cached generations were grown from the previous axiom
+        if stochastic && self.cache.is_some() {
+            self.enable_cache();
+        }
+
+        Ok(())
     }
 
-    /// Iterate the system by `n` steps.
+    /// Move the system back to its current axiom.
+    fn rewind(&mut self) -> Result<(), LSystemError> {
+        self.state.clone_from(&self.axiom);
+        self.fingerprint.take();
+        self.alphabet_counts.take();
+        self.steps = 0;
+        self.sync_history(0)
+    }
+
+    /// Returns the axiom the current state of the system was grown from.
     ///
     /// # Example
     /// ```rust
@@ -169,25 +801,24 @@ impl LSystem {
     /// # let mut builder = LSystemBuilder::new();
     /// # let a = builder.token("a")?;
     /// # let b = builder.token("b")?;
-    /// # builder.axiom(vec![a])?;
-    /// # builder.transformation_rule(a,vec![b, a])?;
+    /// # builder.axiom(vec![a, b])?;
+    /// # builder.transformation_rule(a, vec![a, b])?;
     /// # let mut system = builder.finish()?;
-    /// // `system` is an LSystem with axiom `a` and transformation rule `a -> ba`.
-    /// assert_eq!(system.render(), "a");
-    ///
-    /// // Iterate the system three times
+    /// // `system` is an LSystem with axiom `ab` and transformation rule `a -> ab`.
     /// system.step_by(3);
-    /// assert_eq!(system.render(), "bbba");
+    /// assert_eq!(system.get_axiom(), &[a, b]);
     /// # Ok(())
     /// # }
     /// ```
-    pub fn step_by(&mut self, n: usize) {
-        for _ in 0..n {
-            self.step();
-        }
+    pub fn get_axiom(&self) -> &[ArenaId] {
+        &self.axiom
     }
 
-    /// Returns the number of iterations the system has undergone so far
+    /// Iterate the system a single step.
+    ///
+    /// # Panics
+    /// Panics if there isn't enough memory for the next generation.  Use
+    /// [`LSystem::try_step()`] to handle this case.
     ///
     /// # Example
     /// ```rust
@@ -197,24 +828,29 @@ impl LSystem {
     /// # let a = builder.token("a")?;
     /// # let b = builder.token("b")?;
     /// # builder.axiom(vec![a])?;
-    /// # builder.transformation_rule(a,vec![b, a, b])?;
+    /// # builder.transformation_rule(a,vec![a, b, b, a])?;
     /// # let mut system = builder.finish()?;
-    /// // `system` is an LSystem with axiom `a` and transformation rule `a -> bab`.
-    /// assert_eq!(system.steps(), 0);
+    /// // `system` is an LSystem with axiom `a` and transformation rule `a -> abba`.
     /// assert_eq!(system.render(), "a");
     ///
-    /// // Iterate the system 100 times
-    /// system.step_by(100);
-    /// assert_eq!(system.steps(), 100);
-    /// assert_eq!(system.render().len(), 201);
+    /// // Iterate the system a single time
+    /// system.step();
+    /// assert_eq!(system.render(), "abba");
+    ///
+    /// // Once more for good luck
+    /// system.step();
+    /// assert_eq!(system.render(), "abbabbabba");
     /// # Ok(())
     /// # }
     /// ```
-    pub fn steps(&self) -> usize {
-        self.steps
+    pub fn step(&mut self) {
+        if let Err(e) = self.try_step() {
+            panic!("{}", e);
+        }
     }
 
-    /// Returns the current state of the system as a [`String`].
+    /// Iterate the system a single step, returning an [`LSystemError::AllocationFailed`] error
+    /// (and leaving the system untouched) if there isn't enough memory for the next generation.
     ///
     /// # Example
     /// ```rust
@@ -222,34 +858,88 @@ impl LSystem {
     /// # fn main() -> Result<(), LSystemError> {
     /// # let mut builder = LSystemBuilder::new();
     /// # let a = builder.token("a")?;
-    /// # let b = builder.token("b")?;
     /// # builder.axiom(vec![a])?;
-    /// # builder.transformation_rule(a,vec![a, a])?;
+    /// # builder.transformation_rule(a, vec![a, a])?;
     /// # let mut system = builder.finish()?;
     /// // `system` is an LSystem with axiom `a` and transformation rule `a -> aa`.
-    /// assert_eq!(system.render(), "a");
-    ///
-    /// system.step();
+    /// system.try_step()?;
     /// assert_eq!(system.render(), "aa");
-    ///
-    /// system.step();
-    /// assert_eq!(system.render(), "aaaa");
     /// # Ok(())
     /// # }
     /// ```
-    pub fn render(&self) -> String {
-        self.state
-            .iter()
-            // unwrap: the only way to obtain an LSystem is through one of the builders,
-            //         which verify that all indexes are valid.
-            .map(|id| self.arena.get(*id).unwrap().name())
-            .collect::<Vec<_>>()
-            .join("")
-    }
+    pub fn try_step(&mut self) -> Result<(), LSystemError> {
+        let step_trace = self
+            .trace
+            .is_some()
+            .then(|| self.trace_step(&self.state, self.steps));
+        let predicted = self.predict_next();
+        let mut buffer = self.take_buffer();
 
-    /// Returns a slice consisting of the [`ArenaId`]'s of the tokens currently in the system.
-    ///
-    /// # Example
+        if let Some((len, _)) = predicted {
+            buffer.try_reserve(len)?;
+        }
+
+        // Asking for the fingerprint of one generation suggests it will be asked for again
+        let fingerprinted = self.fingerprint.get().is_some();
+        let (next_state, rules_applied, fingerprint) =
+            self.rewrite_fingerprinted(&self.state, self.steps, buffer, fingerprinted)?;
+        let old_len = self.state.len();
+
+        self.replace_state(next_state);
+
+        if let Some((_, counts)) = predicted {
+            // unwrap: `replace_state` just cleared the counts
+            self.alphabet_counts.set(counts).unwrap();
+        }
+
+        if let Some(fingerprint) = fingerprint {
+            // unwrap: `replace_state` just cleared the fingerprint
+            self.fingerprint.set(fingerprint).unwrap();
+        }
+        self.steps += 1;
+        self.sync_history(self.steps)?;
+
+        if let (Some(trace), Some(step_trace)) = (self.trace.as_mut(), step_trace) {
+            trace.push(step_trace);
+        }
+
+        if let Some(cache) = self.cache.as_mut() {
+            cache.insert(self.steps, self.state.clone());
+            self.trim_cache();
+        }
+
+        if !self.subscribers.is_empty() {
+            let event = StepEvent {
+                generation: self.steps,
+                old_len,
+                new_len: self.state.len(),
+                rules_applied,
+            };
+
+            for subscriber in self.subscribers.callbacks.iter() {
+                subscriber(event);
+            }
+
+            for observer in self.subscribers.observers.iter() {
+                observer
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .on_step(event, &self.state);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the `n`-th generation of the system, computed from the axiom, as a
+    /// [`StateRope`] with chunks of `chunk_len` tokens.  Every intermediate generation is also
+    /// stored as a rope, so no single allocation ever exceeds `chunk_len` tokens.  The current
+    /// state of the system is left untouched.
+    ///
+    /// # Panics
+    /// Panics if `chunk_len` is zero.
+    ///
+    /// # Example
     /// ```rust
     /// # use dcc_lsystem::{LSystemError, LSystemBuilder};
     /// # fn main() -> Result<(), LSystemError> {
@@ -257,16 +947,2600 @@ impl LSystem {
     /// # let a = builder.token("a")?;
     /// # let b = builder.token("b")?;
     /// # builder.axiom(vec![a])?;
-    /// # builder.transformation_rule(a,vec![a, a, b])?;
+    /// # builder.transformation_rule(a, vec![a, b])?;
+    /// # builder.transformation_rule(b, vec![a])?;
+    /// # let system = builder.finish()?;
+    /// // `system` is an LSystem with axiom `a` and transformation rules `a -> ab`, `b -> a`.
+    /// let rope = system.expanded_rope(20, 1024);
+    ///
+    /// assert_eq!(rope.len(), 17711);
+    /// assert!(rope.chunks().all(|chunk| chunk.len() <= 1024));
+    /// assert_eq!(rope.to_vec(), system.expanded(20));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn expanded_rope(&self, n: usize, chunk_len: usize) -> StateRope {
+        let mut rope = StateRope::from_slice(&self.axiom, chunk_len);
+
+        for generation in 0..n {
+            rope = self.rewrite_rope(&rope, generation);
+        }
+
+        rope
+    }
+
+    /// Returns an iterator over the `n`-th generation of the system, computed lazily from the
+    /// axiom.  Tokens are expanded depth first, so no intermediate generation is ever stored and
+    /// memory use only grows with `n` (rather than with the length of the generation).  The
+    /// current state of the system is left untouched.
+    ///
+    /// Systems that rewrite in a sequential [`RewriteMode`], or that have context-sensitive
+    /// rules, need to see a whole generation to rewrite it; for these systems the generation is
+    /// derived in full (as with [`LSystem::expanded()`]) before iterating over it.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::{LSystemError, LSystemBuilder};
+    /// # fn main() -> Result<(), LSystemError> {
+    /// # let mut builder = LSystemBuilder::new();
+    /// # let a = builder.token("a")?;
+    /// # let b = builder.token("b")?;
+    /// # builder.axiom(vec![a])?;
+    /// # builder.transformation_rule(a, vec![a, b])?;
+    /// # builder.transformation_rule(b, vec![a])?;
+    /// # let system = builder.finish()?;
+    /// // `system` is an LSystem with axiom `a` and transformation rules `a -> ab`, `b -> a`.
+    /// let lazy: Vec<_> = system.symbols_at_depth(10).collect();
+    /// assert_eq!(lazy, system.expanded(10));
+    ///
+    /// // Generation 60 has more than 10^12 tokens, but we can still look at the start of it
+    /// let start: Vec<_> = system.symbols_at_depth(60).take(5).collect();
+    /// assert_eq!(start, vec![a, b, a, a, b]);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn symbols_at_depth(&self, n: usize) -> SymbolsAtDepth<'_> {
+        let inner = if self.mode != RewriteMode::Parallel || self.successors.has_contexts() {
+            DepthInner::Expanded(self.expanded(n).into_iter())
+        } else {
+            DepthInner::Lazy {
+                system: self,
+                depth: n,
+                stack: vec![(0, self.axiom.as_slice())],
+                positions: vec![0; n],
+            }
+        };
+
+        SymbolsAtDepth { inner }
+    }
+
+    /// Register a callback that is invoked with a [`StepEvent`] after every step of the system.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::{LSystemError, LSystemBuilder};
+    /// # fn main() -> Result<(), LSystemError> {
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// # let mut builder = LSystemBuilder::new();
+    /// # let a = builder.token("a")?;
+    /// # let b = builder.token("b")?;
+    /// # builder.axiom(vec![a])?;
+    /// # builder.transformation_rule(a, vec![a, b])?;
     /// # let mut system = builder.finish()?;
-    /// // `system` is an LSystem with axiom `a` and transformation rule `a -> aab`.
-    /// assert_eq!(system.get_state(), &[a]);
+    /// // `system` is an LSystem with axiom `a` and transformation rule `a -> ab`.
+    /// let lengths = Arc::new(Mutex::new(Vec::new()));
+    /// let sink = Arc::clone(&lengths);
+    ///
+    /// system.subscribe(move |event| {
+    ///     sink.lock().unwrap().push((event.generation, event.new_len, event.rules_applied));
+    /// });
+    ///
+    /// system.step_by(3);
+    /// assert_eq!(*lengths.lock().unwrap(), vec![(1, 2, 1), (2, 3, 1), (3, 4, 1)]);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn subscribe<F>(&mut self, subscriber: F)
+    where
+        F: Fn(StepEvent) + Send + Sync + 'static,
+    {
+        self.subscribers.callbacks.push(Arc::new(subscriber));
+    }
+
+    /// Register a callback that is invoked after every step of the system with a
+    /// [`StepEvent`] and the new state.  Unlike [`LSystem::subscribe()`], the callback may
+    /// hold mutable state of its own.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::{LSystemError, LSystemBuilder};
+    /// # fn main() -> Result<(), LSystemError> {
+    /// # let mut builder = LSystemBuilder::new();
+    /// # let a = builder.token("a")?;
+    /// # let b = builder.token("b")?;
+    /// # builder.axiom(vec![a])?;
+    /// # builder.transformation_rule(a, vec![a, b])?;
+    /// # let mut system = builder.finish()?;
+    /// // `system` is an LSystem with axiom `a` and transformation rule `a -> ab`.
+    /// let mut total = 0;
+    ///
+    /// system.on_step(move |event, state| {
+    ///     total += state.len();
+    ///     println!("generation {}: {} tokens so far", event.generation, total);
+    /// });
+    ///
+    /// system.step_by(3);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn on_step<F>(&mut self, callback: F)
+    where
+        F: FnMut(StepEvent, &[ArenaId]) + Send + 'static,
+    {
+        self.observe(Arc::new(Mutex::new(callback)));
+    }
+
+    /// Register a [`StepObserver`], which is told about every step of the system.  The
+    /// observer is shared, so the caller can keep a handle to it and read whatever it has
+    /// collected.  Clones of this system share its observers.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::{LSystemError, LSystemBuilder};
+    /// # fn main() -> Result<(), LSystemError> {
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// use dcc_lsystem::system::{StepEvent, StepObserver};
+    /// use dcc_lsystem::ArenaId;
+    ///
+    /// // Records how many `b` tokens there are in each generation
+    /// struct CountB {
+    ///     b: ArenaId,
+    ///     counts: Vec<usize>,
+    /// }
+    ///
+    /// impl StepObserver for CountB {
+    ///     fn on_step(&mut self, _event: StepEvent, state: &[ArenaId]) {
+    ///         self.counts.push(state.iter().filter(|&&id| id == self.b).count());
+    ///     }
+    /// }
+    ///
+    /// # let mut builder = LSystemBuilder::new();
+    /// # let a = builder.token("a")?;
+    /// # let b = builder.token("b")?;
+    /// # builder.axiom(vec![a])?;
+    /// # builder.transformation_rule(a, vec![a, b])?;
+    /// # let mut system = builder.finish()?;
+    /// // `system` is an LSystem with axiom `a` and transformation rule `a -> ab`.
+    /// let observer = Arc::new(Mutex::new(CountB { b, counts: Vec::new() }));
+    /// system.observe(observer.clone());
+    ///
+    /// system.step_by(3);
+    /// assert_eq!(observer.lock().unwrap().counts, vec![1, 2, 3]);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn observe<O>(&mut self, observer: Arc<Mutex<O>>)
+    where
+        O: StepObserver + 'static,
+    {
+        self.subscribers.observers.push(observer);
+    }
+
+    /// Returns the `n`-th generation of the system, computed from the axiom, without
+    /// changing the current state of the system.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::{LSystemError, LSystemBuilder};
+    /// # fn main() -> Result<(), LSystemError> {
+    /// # let mut builder = LSystemBuilder::new();
+    /// # let a = builder.token("a")?;
+    /// # let b = builder.token("b")?;
+    /// # builder.axiom(vec![a])?;
+    /// # builder.transformation_rule(a, vec![a, b])?;
+    /// # let mut system = builder.finish()?;
+    /// // `system` is an LSystem with axiom `a` and transformation rule `a -> ab`.
     /// system.step();
-    /// assert_eq!(system.get_state(), &[a, a, b]);
+    ///
+    /// assert_eq!(system.expanded(0), vec![a]);
+    /// assert_eq!(system.expanded(3), vec![a, b, b, b]);
+    ///
+    /// // The system itself hasn't moved
+    /// assert_eq!(system.steps(), 1);
+    /// assert_eq!(system.render(), "ab");
     /// # Ok(())
     /// # }
     /// ```
-    pub fn get_state(&self) -> &[ArenaId] {
-        &self.state
+    pub fn expanded(&self, n: usize) -> Vec<ArenaId> {
+        self.derive(&self.axiom, n)
+    }
+
+    /// Returns `true` if this system and `other` produce the same generations, from the axiom up
+    /// to and including generation `depth`.  Tokens are compared by name, so two systems whose
+    /// tokens were registered in a different order can still be equivalent.  The current states
+    /// of the systems play no part in the comparison.
+    ///
+    /// # Panics
+    /// Panics if there isn't enough memory for one of the generations.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::{LSystemError, LSystemBuilder};
+    /// # fn main() -> Result<(), LSystemError> {
+    /// // a -> ab, b -> a
+    /// let mut builder = LSystemBuilder::new();
+    /// let a = builder.token("a")?;
+    /// let b = builder.token("b")?;
+    /// builder.axiom(vec![a])?;
+    /// builder.transformation_rule(a, vec![a, b])?;
+    /// builder.transformation_rule(b, vec![a])?;
+    /// let first = builder.finish()?;
+    ///
+    /// // The same rules, with the tokens registered the other way round
+    /// let mut builder = LSystemBuilder::new();
+    /// let b = builder.token("b")?;
+    /// let a = builder.token("a")?;
+    /// builder.axiom(vec![a])?;
+    /// builder.transformation_rule(b, vec![a])?;
+    /// builder.transformation_rule(a, vec![a, b])?;
+    /// let second = builder.finish()?;
+    ///
+    /// assert!(first.equivalent_to(&second, 10));
+    ///
+    /// // a -> ab, b -> b only agrees for the first two generations
+    /// let mut builder = LSystemBuilder::new();
+    /// let a = builder.token("a")?;
+    /// let b = builder.token("b")?;
+    /// builder.axiom(vec![a])?;
+    /// builder.transformation_rule(a, vec![a, b])?;
+    /// let third = builder.finish()?;
+    ///
+    /// assert!(first.equivalent_to(&third, 1));
+    /// assert!(!first.equivalent_to(&third, 2));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn equivalent_to(&self, other: &LSystem, depth: usize) -> bool {
+        let mut state = self.axiom.clone();
+        let mut other_state = other.axiom.clone();
+
+        for generation in 0..=depth {
+            let same = state.len() == other_state.len()
+                && state
+                    .iter()
+                    .zip(other_state.iter())
+                    .all(|(&id, &other_id)| self.names.get(id) == other.names.get(other_id));
+
+            if !same {
+                return false;
+            }
+
+            if generation < depth {
+                state = self.derive_next(&state, generation);
+                other_state = other.derive_next(&other_state, generation);
+            }
+        }
+
+        true
+    }
+
+    /// Returns, for each token of the `n`-th generation of the system, the generation in which
+    /// the token was produced.  Tokens of the axiom were produced in generation `0`, and a token
+    /// keeps its age for as long as it is only rewritten to itself.  The ages line up with the
+    /// tokens of [`LSystem::expanded()`], and can be used (for example) to color a drawing by
+    /// age.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::{LSystemError, LSystemBuilder};
+    /// # fn main() -> Result<(), LSystemError> {
+    /// # let mut builder = LSystemBuilder::new();
+    /// # let a = builder.token("a")?;
+    /// # let b = builder.token("b")?;
+    /// # builder.axiom(vec![a])?;
+    /// # builder.transformation_rule(a, vec![a, b])?;
+    /// # let system = builder.finish()?;
+    /// // `system` is an LSystem with axiom `a` and transformation rule `a -> ab`.
+    /// assert_eq!(system.expanded(3), vec![a, b, b, b]);
+    /// assert_eq!(system.birth_generations(3), vec![3, 3, 2, 1]);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn birth_generations(&self, n: usize) -> Vec<usize> {
+        let mut state = self.axiom.clone();
+        let mut births = vec![0; state.len()];
+
+        for generation in 0..n {
+            let mut next_state = Vec::with_capacity(state.len());
+            let mut next_births = Vec::with_capacity(state.len());
+
+            for (position, successor) in self.successors_of(&state, generation).enumerate() {
+                let id = state[position];
+
+                match successor {
+                    Some(successor) if successor != [id] => {
+                        next_state.extend_from_slice(successor);
+                        next_births.extend(std::iter::repeat_n(generation + 1, successor.len()));
+                    }
+                    _ => {
+                        next_state.push(id);
+                        next_births.push(births[position]);
+                    }
+                }
+            }
+
+            state = next_state;
+            births = next_births;
+        }
+
+        births
+    }
+
+    /// Iterate the system by `n` steps.
+    ///
+    /// # Panics
+    /// Panics if there isn't enough memory for one of the generations.  Use
+    /// [`LSystem::try_step_by()`] to handle this case.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::{LSystemError, LSystemBuilder};
+    /// # fn main() -> Result<(), LSystemError> {
+    /// # let mut builder = LSystemBuilder::new();
+    /// # let a = builder.token("a")?;
+    /// # let b = builder.token("b")?;
+    /// # builder.axiom(vec![a])?;
+    /// # builder.transformation_rule(a,vec![b, a])?;
+    /// # let mut system = builder.finish()?;
+    /// // `system` is an LSystem with axiom `a` and transformation rule `a -> ba`.
+    /// assert_eq!(system.render(), "a");
+    ///
+    /// // Iterate the system three times
+    /// system.step_by(3);
+    /// assert_eq!(system.render(), "bbba");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn step_by(&mut self, n: usize) {
+        if let Err(e) = self.try_step_by(n) {
+            panic!("{}", e);
+        }
+    }
+
+    /// Iterate the system by `n` steps, stopping with an [`LSystemError::AllocationFailed`]
+    /// error if there isn't enough memory for one of the generations.  The system is left at
+    /// the last generation that could be allocated.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::{LSystemError, LSystemBuilder};
+    /// # fn main() -> Result<(), LSystemError> {
+    /// # let mut builder = LSystemBuilder::new();
+    /// # let a = builder.token("a")?;
+    /// # builder.axiom(vec![a])?;
+    /// # builder.transformation_rule(a, vec![a, a])?;
+    /// # let mut system = builder.finish()?;
+    /// // `system` is an LSystem with axiom `a` and transformation rule `a -> aa`.
+    /// if let Err(LSystemError::AllocationFailed(_)) = system.try_step_by(10) {
+    ///     // Fall back to something smaller
+    /// }
+    ///
+    /// assert_eq!(system.steps(), 10);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn try_step_by(&mut self, n: usize) -> Result<(), LSystemError> {
+        if n > 1 && self.can_jump() {
+            let buffer = self.take_buffer();
+            let next_state = self.expand_memoized(n, buffer)?;
+
+            self.replace_state(next_state);
+            self.steps += n;
+            return Ok(());
+        }
+
+        for _ in 0..n {
+            self.try_step()?;
+        }
+
+        Ok(())
+    }
+
+    /// Iterate the system by `n` steps, stopping with an [`LSystemError::SymbolLimitExceeded`]
+    /// error before any generation with more than `max_symbols` tokens is built.  The system is
+    /// left at the last generation that fit within the limit.
+    ///
+    /// This guards against grammars that grow far faster than expected: the size of each
+    /// generation is worked out before any memory is allocated for it.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::{LSystemError, LSystemBuilder};
+    /// # fn main() -> Result<(), LSystemError> {
+    /// # let mut builder = LSystemBuilder::new();
+    /// # let a = builder.token("a")?;
+    /// # builder.axiom(vec![a])?;
+    /// # builder.transformation_rule(a, vec![a, a])?;
+    /// # let mut system = builder.finish()?;
+    /// // `system` is an LSystem with axiom `a` and transformation rule `a -> aa`.
+    /// let result = system.step_by_checked(100, 1000);
+    ///
+    /// assert!(matches!(
+    ///     result,
+    ///     Err(LSystemError::SymbolLimitExceeded { len: 1024, .. })
+    /// ));
+    /// assert_eq!(system.steps(), 9);
+    /// assert_eq!(system.get_state().len(), 512);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn step_by_checked(&mut self, n: usize, max_symbols: usize) -> Result<(), LSystemError> {
+        for _ in 0..n {
+            self.check_next_len(max_symbols)?;
+            self.try_step()?;
+        }
+
+        Ok(())
+    }
+
+    /// Iterate the system until `done` returns `true`, returning the number of steps taken.
+    /// `done` is called with the system before every step, so no steps are taken if it is
+    /// already satisfied.
+    ///
+    /// As with [`LSystem::step_by_checked()`], this stops with an
+    /// [`LSystemError::SymbolLimitExceeded`] error before building a generation with more than
+    /// `max_symbols` tokens.  Note that a system which stops growing before `done` is
+    /// satisfied will be stepped forever.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::{LSystemError, LSystemBuilder};
+    /// # fn main() -> Result<(), LSystemError> {
+    /// # let mut builder = LSystemBuilder::new();
+    /// # let a = builder.token("a")?;
+    /// # let b = builder.token("b")?;
+    /// # builder.axiom(vec![a])?;
+    /// # builder.transformation_rule(a, vec![a, b])?;
+    /// # let mut system = builder.finish()?;
+    /// // `system` is an LSystem with axiom `a` and transformation rule `a -> ab`.
+    /// let steps = system.step_until(|system| system.get_state().len() >= 5, 1000)?;
+    ///
+    /// assert_eq!(steps, 4);
+    /// assert_eq!(system.render(), "abbbb");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn step_until<F>(&mut self, mut done: F, max_symbols: usize) -> Result<usize, LSystemError>
+    where
+        F: FnMut(&LSystem) -> bool,
+    {
+        let mut steps = 0;
+
+        while !done(self) {
+            self.check_next_len(max_symbols)?;
+            self.try_step()?;
+            steps += 1;
+        }
+
+        Ok(steps)
+    }
+
+    /// Returns an [`LSystemError::SymbolLimitExceeded`] error if the next generation of the
+    /// system has more than `max_symbols` tokens.
+    fn check_next_len(&self, max_symbols: usize) -> Result<(), LSystemError> {
+        let len = self.next_generation_len();
+
+        if len > max_symbols {
+            return Err(LSystemError::SymbolLimitExceeded {
+                generation: self.steps + 1,
+                len,
+                limit: max_symbols,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Returns the number of iterations the system has undergone so far
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::{LSystemError, LSystemBuilder};
+    /// # fn main() -> Result<(), LSystemError> {
+    /// # let mut builder = LSystemBuilder::new();
+    /// # let a = builder.token("a")?;
+    /// # let b = builder.token("b")?;
+    /// # builder.axiom(vec![a])?;
+    /// # builder.transformation_rule(a,vec![b, a, b])?;
+    /// # let mut system = builder.finish()?;
+    /// // `system` is an LSystem with axiom `a` and transformation rule `a -> bab`.
+    /// assert_eq!(system.steps(), 0);
+    /// assert_eq!(system.render(), "a");
+    ///
+    /// // Iterate the system 100 times
+    /// system.step_by(100);
+    /// assert_eq!(system.steps(), 100);
+    /// assert_eq!(system.render().len(), 201);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    /// Returns the current state of the system as a [`String`].
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::{LSystemError, LSystemBuilder};
+    /// # fn main() -> Result<(), LSystemError> {
+    /// # let mut builder = LSystemBuilder::new();
+    /// # let a = builder.token("a")?;
+    /// # let b = builder.token("b")?;
+    /// # builder.axiom(vec![a])?;
+    /// # builder.transformation_rule(a,vec![a, a])?;
+    /// # let mut system = builder.finish()?;
+    /// // `system` is an LSystem with axiom `a` and transformation rule `a -> aa`.
+    /// assert_eq!(system.render(), "a");
+    ///
+    /// system.step();
+    /// assert_eq!(system.render(), "aa");
+    ///
+    /// system.step();
+    /// assert_eq!(system.render(), "aaaa");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn render(&self) -> String {
+        self.names.render(self.state.iter().copied())
+    }
+
+    /// Returns the current state of the system as a [`String`], with `separator` between the
+    /// names of consecutive tokens.  Unlike [`LSystem::render()`], the output can be read back
+    /// unambiguously with [`LSystem::parse_state()`] when token names are longer than a single
+    /// character, as long as no token name contains `separator` (which `parse_state()` reports
+    /// as an error).
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::{LSystemError, LSystemBuilder};
+    /// # fn main() -> Result<(), LSystemError> {
+    /// # let mut builder = LSystemBuilder::new();
+    /// # let stem = builder.token("Stem")?;
+    /// # let leaf = builder.token("Leaf")?;
+    /// # builder.axiom(vec![stem])?;
+    /// # builder.transformation_rule(stem, vec![stem, leaf])?;
+    /// # let mut system = builder.finish()?;
+    /// // `system` is an LSystem with axiom `Stem` and transformation rule `Stem -> Stem Leaf`.
+    /// system.step_by(2);
+    ///
+    /// assert_eq!(system.render(), "StemLeafLeaf");
+    /// assert_eq!(system.render_with_separator(" "), "Stem Leaf Leaf");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn render_with_separator(&self, separator: &str) -> String {
+        let names = self.state.iter().map(|&id| self.names.get(id));
+
+        // Work out the final length first, so that we only allocate once.
+        let len = names.clone().map(str::len).sum::<usize>()
+            + separator.len() * self.state.len().saturating_sub(1);
+        let mut rendered = String::with_capacity(len);
+
+        for (index, name) in names.enumerate() {
+            if index > 0 {
+                rendered.push_str(separator);
+            }
+
+            rendered.push_str(name);
+        }
+
+        rendered
+    }
+
+    /// Reads back a state written by [`LSystem::render_with_separator()`], returning the
+    /// tokens it names.  With an empty `separator` (as written by [`LSystem::render()`]) the
+    /// text is split by repeatedly taking the longest token name it starts with, which can
+    /// fail to recover the original tokens if one token name is a prefix of another.
+    ///
+    /// Returns an [`LSystemError::AmbiguousSeparator`] error if `separator` isn't empty and
+    /// some token name contains it, since `text` can't be split unambiguously, or an
+    /// [`LSystemError::UnknownToken`] error if some part of `text` isn't the name of a token
+    /// of this system.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::{LSystemError, LSystemBuilder};
+    /// # fn main() -> Result<(), LSystemError> {
+    /// # let mut builder = LSystemBuilder::new();
+    /// # let stem = builder.token("Stem")?;
+    /// # let leaf = builder.token("Leaf")?;
+    /// # builder.axiom(vec![stem])?;
+    /// # builder.transformation_rule(stem, vec![stem, leaf])?;
+    /// # let mut system = builder.finish()?;
+    /// // `system` is an LSystem with axiom `Stem` and transformation rule `Stem -> Stem Leaf`.
+    /// system.step_by(2);
+    ///
+    /// let rendered = system.render_with_separator(", ");
+    /// assert_eq!(system.parse_state(&rendered, ", ")?, system.get_state());
+    /// assert_eq!(system.parse_state("LeafStem", "")?, vec![leaf, stem]);
+    /// assert!(matches!(
+    ///     system.parse_state("Leaf, Root", ", "),
+    ///     Err(LSystemError::UnknownToken(name)) if name == "Root"
+    /// ));
+    ///
+    /// // `Stem` contains `e`, so splitting at every `e` wouldn't give back the tokens
+    /// assert!(matches!(
+    ///     system.parse_state("SteemeLeaf", "e"),
+    ///     Err(LSystemError::AmbiguousSeparator { .. })
+    /// ));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn parse_state(&self, text: &str, separator: &str) -> Result<Vec<ArenaId>, LSystemError> {
+        let unknown = |name: &str| LSystemError::UnknownToken(name.to_string());
+
+        if separator.is_empty() {
+            let mut ids = Vec::new();
+            let mut rest = text;
+
+            while !rest.is_empty() {
+                // Later tokens win ties, to agree with `LSystemBuilder::get_token()`
+                let (id, len) = self
+                    .arena
+                    .enumerate()
+                    .map(|(id, token)| (id, token.name()))
+                    .filter(|(_, name)| !name.is_empty() && rest.starts_with(name))
+                    .map(|(id, name)| (id, name.len()))
+                    .max_by_key(|&(_, len)| len)
+                    .ok_or_else(|| unknown(rest))?;
+
+                ids.push(id);
+                rest = &rest[len..];
+            }
+
+            return Ok(ids);
+        }
+
+        if let Some(token) = self
+            .arena
+            .iter()
+            .find(|token| token.name().contains(separator))
+        {
+            return Err(LSystemError::AmbiguousSeparator {
+                token: token.name().to_string(),
+                separator: separator.to_string(),
+            });
+        }
+
+        if text.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let ids: HashMap<&str, ArenaId> = self
+            .arena
+            .enumerate()
+            .map(|(id, token)| (token.name(), id))
+            .collect();
+
+        text.split(separator)
+            .map(|name| ids.get(name).copied().ok_or_else(|| unknown(name)))
+            .collect()
+    }
+
+    /// Writes the current state of the system to `writer`, one token name at a time, without
+    /// building the whole rendered [`String`] in memory.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::{LSystemError, LSystemBuilder};
+    /// # fn main() -> Result<(), LSystemError> {
+    /// # let mut builder = LSystemBuilder::new();
+    /// # let a = builder.token("a")?;
+    /// # let b = builder.token("b")?;
+    /// # builder.axiom(vec![a])?;
+    /// # builder.transformation_rule(a, vec![a, b])?;
+    /// # let mut system = builder.finish()?;
+    /// // `system` is an LSystem with axiom `a` and transformation rule `a -> ab`.
+    /// system.step_by(2);
+    ///
+    /// let mut output = String::from("state: ");
+    /// system.write_to(&mut output).unwrap();
+    /// assert_eq!(output, "state: abb");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn write_to<W: std::fmt::Write>(&self, writer: &mut W) -> std::fmt::Result {
+        for name in self.token_names() {
+            writer.write_str(name)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns an iterator over the names of the tokens currently in the system.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::{LSystemError, LSystemBuilder};
+    /// # fn main() -> Result<(), LSystemError> {
+    /// let mut builder = LSystemBuilder::new();
+    /// let leaf = builder.token("Leaf")?;
+    /// let stem = builder.token("Stem")?;
+    /// builder.axiom(vec![leaf])?;
+    /// builder.transformation_rule(leaf, vec![stem, leaf])?;
+    ///
+    /// let mut system = builder.finish()?;
+    /// system.step();
+    ///
+    /// let names: Vec<&str> = system.token_names().collect();
+    /// assert_eq!(names, vec!["Stem", "Leaf"]);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn token_names(&self) -> impl Iterator<Item = &str> + '_ {
+        self.state.iter().map(move |&id| self.names.get(id))
+    }
+
+    /// Returns the [`ArenaId`]'s of the tokens currently in the system.  This is the same as
+    /// [`LSystem::get_state()`].
+    pub fn state(&self) -> &[ArenaId] {
+        &self.state
+    }
+
+    /// Returns a 64-bit fingerprint of the current state of the system, for use as (say) the
+    /// key of a cache of rendered images.  Equal states have equal fingerprints, and different
+    /// states almost always have different ones.
+    ///
+    /// The fingerprint only depends on the names of the tokens in the state, so it is stable
+    /// between runs and platforms, and two systems with the same state have the same
+    /// fingerprint whatever order their tokens were registered in.
+    ///
+    /// Once the fingerprint has been asked for, each following step of a system rewritten in
+    /// parallel works out the fingerprint of the new state as it is written, so that asking
+    /// for it after every step doesn't hash the state all over again.  Otherwise it is worked
+    /// out the first time it is asked for, and then remembered until the state changes.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::{LSystemError, LSystemBuilder};
+    /// # fn main() -> Result<(), LSystemError> {
+    /// # let mut builder = LSystemBuilder::new();
+    /// # let a = builder.token("a")?;
+    /// # let b = builder.token("b")?;
+    /// # builder.axiom(vec![a])?;
+    /// # builder.transformation_rule(a, vec![a, b])?;
+    /// # builder.transformation_rule(b, vec![a])?;
+    /// # let mut system = builder.finish()?;
+    /// // `system` is an LSystem with axiom `a` and transformation rules `a -> ab`, `b -> a`.
+    /// let start = system.state_fingerprint();
+    ///
+    /// system.step_by(5);
+    /// let fingerprint = system.state_fingerprint();
+    /// assert_ne!(fingerprint, start);
+    ///
+    /// system.reset();
+    /// assert_eq!(system.state_fingerprint(), start);
+    /// system.step_by(5);
+    /// assert_eq!(system.state_fingerprint(), fingerprint);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn state_fingerprint(&self) -> u64 {
+        *self
+            .fingerprint
+            .get_or_init(|| self.names.fingerprint(&self.state))
+    }
+
+    /// Set the interpretation of `id` to `tokens`, replacing any previous interpretation.
+    /// See [`LSystemBuilder::interpretation()`](crate::LSystemBuilder::interpretation()).
+    ///
+    /// This function will return an [`LSystemError::InvalidArenaId`] error if any of the ids
+    /// are invalid.
+    pub fn set_interpretation(
+        &mut self,
+        id: ArenaId,
+        tokens: Vec<ArenaId>,
+    ) -> Result<(), LSystemError> {
+        if let Some(&id) = std::iter::once(&id)
+            .chain(tokens.iter())
+            .find(|&&id| !self.arena.is_valid(id))
+        {
+            return Err(LSystemError::InvalidArenaId(id));
+        }
+
+        self.interpretations.insert(id, tokens);
+
+        Ok(())
+    }
+
+    /// Remove the interpretation of `id`, so that it is interpreted as itself.
+    pub fn clear_interpretation(&mut self, id: ArenaId) {
+        self.interpretations.remove(&id);
+    }
+
+    /// Returns an iterator over the current state with every token replaced by its
+    /// interpretation.  Tokens without an interpretation are left as they are.  This is the
+    /// sequence of tokens a renderer should draw.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::{LSystemError, LSystemBuilder};
+    /// # fn main() -> Result<(), LSystemError> {
+    /// # let mut builder = LSystemBuilder::new();
+    /// # let a = builder.token("a")?;
+    /// # let b = builder.token("b")?;
+    /// # builder.axiom(vec![a])?;
+    /// # builder.transformation_rule(a, vec![a, b])?;
+    /// # let mut system = builder.finish()?;
+    /// // `system` is an LSystem with axiom `a` and transformation rule `a -> ab`.
+    /// system.step_by(2);
+    ///
+    /// // Hide `a`
+    /// system.set_interpretation(a, vec![])?;
+    /// assert_eq!(system.interpreted().collect::<Vec<_>>(), vec![b, b]);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn interpreted(&self) -> impl Iterator<Item = ArenaId> + Clone + '_ {
+        self.state.iter().flat_map(move |id| {
+            match self.interpretations.get(id) {
+                Some(tokens) => tokens.as_slice(),
+                None => std::slice::from_ref(id),
+            }
+            .iter()
+            .copied()
+        })
+    }
+
+    /// Returns the interpreted state of the system (see [`LSystem::interpreted()`]) as a
+    /// [`String`].
+    pub fn render_interpreted(&self) -> String {
+        self.names.render(self.interpreted())
+    }
+
+    /// Returns a slice consisting of the [`ArenaId`]'s of the tokens currently in the system.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::{LSystemError, LSystemBuilder};
+    /// # fn main() -> Result<(), LSystemError> {
+    /// # let mut builder = LSystemBuilder::new();
+    /// # let a = builder.token("a")?;
+    /// # let b = builder.token("b")?;
+    /// # builder.axiom(vec![a])?;
+    /// # builder.transformation_rule(a,vec![a, a, b])?;
+    /// # let mut system = builder.finish()?;
+    /// // `system` is an LSystem with axiom `a` and transformation rule `a -> aab`.
+    /// assert_eq!(system.get_state(), &[a]);
+    /// system.step();
+    /// assert_eq!(system.get_state(), &[a, a, b]);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn get_state(&self) -> &[ArenaId] {
+        &self.state
+    }
+
+    /// Returns the [`RewriteMode`] used by this system.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::{LSystemError, LSystemBuilder};
+    /// # fn main() -> Result<(), LSystemError> {
+    /// use dcc_lsystem::system::RewriteMode;
+    ///
+    /// let mut builder = LSystemBuilder::new();
+    /// let a = builder.token("a")?;
+    /// builder.axiom(vec![a])?;
+    /// let system = builder.finish()?;
+    ///
+    /// // Systems rewrite in parallel unless told otherwise.
+    /// assert_eq!(system.rewrite_mode(), RewriteMode::Parallel);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn rewrite_mode(&self) -> RewriteMode {
+        self.mode
+    }
+
+    /// Returns the seed this system uses for its random choices.
+    ///
+    /// Each generation draws from its own stream derived from the seed and the generation
+    /// index, so generation `n` always comes out the same way for a given seed, no matter how
+    /// many times the system has been reset or stepped along the way.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::{LSystemError, LSystemBuilder};
+    /// # fn main() -> Result<(), LSystemError> {
+    /// let mut builder = LSystemBuilder::new();
+    /// let a = builder.token("a")?;
+    /// builder.axiom(vec![a])?;
+    /// builder.seed(7);
+    ///
+    /// let system = builder.finish()?;
+    /// assert_eq!(system.seed(), 7);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Change the seed this system uses for its random choices.  Generations produced after
+    /// this call use streams derived from the new seed; the current state is left untouched.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::{LSystemError, LSystemBuilder};
+    /// # fn main() -> Result<(), LSystemError> {
+    /// let mut builder = LSystemBuilder::new();
+    /// let a = builder.token("a")?;
+    /// builder.axiom(vec![a])?;
+    ///
+    /// let mut system = builder.finish()?;
+    /// system.set_seed(99);
+    /// assert_eq!(system.seed(), 99);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+
+        // Cached generations may have been produced using the old seed
+        if self.cache.is_some() {
+            self.enable_cache();
+        }
+    }
+
+    /// Returns the number of threads used to expand large states.
+    pub fn threads(&self) -> usize {
+        self.threads.max(1)
+    }
+
+    /// Set the number of threads used to expand the state when rewriting in
+    /// [`RewriteMode::Parallel`].  Large states are split into roughly equal chunks that are
+    /// expanded concurrently and then concatenated in order; small states are always expanded
+    /// on the calling thread.  Passing `0` or `1` disables multi-threaded expansion.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::{LSystemError, LSystemBuilder};
+    /// # fn main() -> Result<(), LSystemError> {
+    /// # let mut builder = LSystemBuilder::new();
+    /// # let a = builder.token("a")?;
+    /// # let b = builder.token("b")?;
+    /// # builder.axiom(vec![a])?;
+    /// # builder.transformation_rule(a, vec![a, b])?;
+    /// # builder.transformation_rule(b, vec![a])?;
+    /// # let mut system = builder.finish()?;
+    /// // `system` is an LSystem with axiom `a` and transformation rules `a -> ab`, `b -> a`.
+    /// let mut threaded = system.clone();
+    /// threaded.set_threads(4);
+    ///
+    /// system.step_by(25);
+    /// threaded.step_by(25);
+    /// assert_eq!(system.get_state(), threaded.get_state());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = threads;
+    }
+
+    /// Turn memoized expansion on or off.  While it is on, [`LSystem::step_by()`] (and
+    /// [`LSystem::try_step_by()`]) take all their steps at once for D0L-systems: every token is
+    /// expanded the requested number of generations deep exactly once, and the new state is
+    /// assembled by copying those expansions rather than by rewriting one generation after
+    /// another.  This is much faster for deep derivations of systems with small alphabets.
+    ///
+    /// Memoization only applies to systems rewritten in [`RewriteMode::Parallel`] with a
+    /// single context-free successor for every token and no rule table schedule.  It is
+    /// skipped while anything needs to see every generation, that is while there are step
+    /// callbacks or observers, the generation cache is enabled, or count history is being
+    /// kept.  Unlike stepping one generation at a time, running out of memory leaves the
+    /// system where it started.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::{LSystemError, LSystemBuilder};
+    /// # fn main() -> Result<(), LSystemError> {
+    /// # let mut builder = LSystemBuilder::new();
+    /// # let a = builder.token("a")?;
+    /// # let b = builder.token("b")?;
+    /// # builder.axiom(vec![a])?;
+    /// # builder.transformation_rule(a, vec![a, b])?;
+    /// # builder.transformation_rule(b, vec![a])?;
+    /// # let mut system = builder.finish()?;
+    /// // `system` is an LSystem with axiom `a` and transformation rules `a -> ab`, `b -> a`.
+    /// let mut memoized = system.clone();
+    /// memoized.set_memoize(true);
+    /// assert!(memoized.memoized());
+    ///
+    /// system.step_by(25);
+    /// memoized.step_by(25);
+    /// assert_eq!(system.get_state(), memoized.get_state());
+    /// assert_eq!(memoized.steps(), 25);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_memoize(&mut self, memoize: bool) {
+        self.memoize = memoize;
+    }
+
+    /// Returns `true` if memoized expansion is turned on.  See [`LSystem::set_memoize()`].
+    pub fn memoized(&self) -> bool {
+        self.memoize
+    }
+
+    /// Tell the system to expect generations of up to `tokens` tokens, or remove the hint
+    /// with `None`.
+    ///
+    /// While there is a hint, new state buffers are allocated with room for at least that many
+    /// tokens, and the buffer of each discarded state is kept and reused for the next step.
+    /// Stepping then doesn't allocate at all until the hint is exceeded, at the cost of holding
+    /// memory for two generations at once.  [`LSystem::predicted_len()`] is a good source of
+    /// hints for deterministic systems.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::{LSystemError, LSystemBuilder};
+    /// # fn main() -> Result<(), LSystemError> {
+    /// # let mut builder = LSystemBuilder::new();
+    /// # let a = builder.token("a")?;
+    /// # let b = builder.token("b")?;
+    /// # builder.axiom(vec![a])?;
+    /// # builder.transformation_rule(a, vec![a, b])?;
+    /// # builder.transformation_rule(b, vec![a])?;
+    /// # let mut system = builder.finish()?;
+    /// // `system` is an LSystem with axiom `a` and transformation rules `a -> ab`, `b -> a`.
+    /// let hint = system.predicted_len(20).map(|len| len as usize);
+    /// system.set_capacity_hint(hint);
+    ///
+    /// system.step_by(20);
+    /// assert_eq!(system.get_state().len(), 17711);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_capacity_hint(&mut self, tokens: Option<usize>) {
+        self.capacity_hint = tokens;
+
+        if tokens.is_none() {
+            let spare = std::mem::take(&mut self.spare);
+
+            if let Some(pool) = self.pool.as_ref() {
+                pool.give(spare);
+            }
+        }
+    }
+
+    /// Start caching the state of every generation this system produces, so that
+    /// [`LSystem::seek()`] can return to previously visited generations without re-deriving them.
+    /// Calling this on a system that is already caching discards everything except the axiom and
+    /// the current state.
+    ///
+    /// Note that the cache holds a full copy of every generation, so this is best suited to
+    /// systems of moderate size.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::{LSystemError, LSystemBuilder};
+    /// # fn main() -> Result<(), LSystemError> {
+    /// # let mut builder = LSystemBuilder::new();
+    /// # let a = builder.token("a")?;
+    /// # let b = builder.token("b")?;
+    /// # builder.axiom(vec![a])?;
+    /// # builder.transformation_rule(a, vec![a, b])?;
+    /// # let mut system = builder.finish()?;
+    /// // `system` is an LSystem with axiom `a` and transformation rule `a -> ab`.
+    /// system.enable_cache();
+    /// system.step_by(5);
+    /// assert_eq!(system.cached_generations(), vec![0, 1, 2, 3, 4, 5]);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn enable_cache(&mut self) {
+        let mut cache = BTreeMap::new();
+        cache.insert(0, self.axiom.clone());
+        cache.insert(self.steps, self.state.clone());
+
+        self.cache = Some(cache);
+        self.trim_cache();
+    }
+
+    /// Stop caching generations, and free any cached states.
+    pub fn disable_cache(&mut self) {
+        self.cache = None;
+    }
+
+    /// Returns the generations currently held in the cache, in increasing order.  If caching
+    /// is disabled this is empty.
+    pub fn cached_generations(&self) -> Vec<usize> {
+        self.cache
+            .as_ref()
+            .map(|cache| cache.keys().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Returns the number of times each token occurs in the current state.  Tokens that
+    /// don't occur are left out.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::{LSystemError, LSystemBuilder};
+    /// # fn main() -> Result<(), LSystemError> {
+    /// # let mut builder = LSystemBuilder::new();
+    /// # let a = builder.token("a")?;
+    /// # let b = builder.token("b")?;
+    /// # builder.axiom(vec![a])?;
+    /// # builder.transformation_rule(a, vec![a, b])?;
+    /// # let mut system = builder.finish()?;
+    /// // `system` is an LSystem with axiom `a` and transformation rule `a -> ab`.
+    /// system.step_by(4);
+    ///
+    /// let counts = system.token_counts();
+    /// assert_eq!(counts[&a], 1);
+    /// assert_eq!(counts[&b], 4);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn token_counts(&self) -> HashMap<ArenaId, usize> {
+        match self
+            .count_history
+            .as_ref()
+            .and_then(|history| history.last())
+        {
+            Some(counts) => counts.clone(),
+            None => count_tokens(&self.state),
+        }
+    }
+
+    /// Start recording the token counts of every generation, which can be read back with
+    /// [`LSystem::count_history()`].  The counts of the generations up to the current one are
+    /// filled in straight away, and from then on the counts of each new generation are
+    /// recorded as it is stepped into.
+    ///
+    /// The generations before the current one that aren't cached are derived again, so this
+    /// returns an [`LSystemError::AllocationFailed`] error (and leaves the history disabled)
+    /// if there isn't enough memory for one of them.
+    pub fn enable_count_history(&mut self) -> Result<(), LSystemError> {
+        if self.count_history.is_none() {
+            self.count_history = Some(Vec::new());
+
+            if let Err(e) = self.sync_count_history(0) {
+                self.count_history = None;
+                return Err(e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Stop recording token counts, and forget the recorded history.
+    pub fn disable_count_history(&mut self) {
+        self.count_history = None;
+    }
+
+    /// Returns the token counts of each generation from the axiom up to the current state,
+    /// indexed by generation, or `None` if the history isn't enabled (see
+    /// [`LSystem::enable_count_history()`]).
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::{LSystemError, LSystemBuilder};
+    /// # fn main() -> Result<(), LSystemError> {
+    /// # let mut builder = LSystemBuilder::new();
+    /// # let a = builder.token("a")?;
+    /// # let b = builder.token("b")?;
+    /// # builder.axiom(vec![a])?;
+    /// # builder.transformation_rule(a, vec![a, b])?;
+    /// # builder.transformation_rule(b, vec![a])?;
+    /// # let mut system = builder.finish()?;
+    /// // `system` is an LSystem with axiom `a` and transformation rules `a -> ab`, `b -> a`.
+    /// system.enable_count_history()?;
+    /// system.step_by(5);
+    ///
+    /// let history = system.count_history().unwrap();
+    /// let a_counts: Vec<usize> = history.iter().map(|counts| counts[&a]).collect();
+    /// assert_eq!(a_counts, vec![1, 1, 2, 3, 5, 8]);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn count_history(&self) -> Option<&[HashMap<ArenaId, usize>]> {
+        self.count_history.as_deref()
+    }
+
+    /// Start recording the production rules applied by each step, which can be read back with
+    /// [`LSystem::trace()`].  Only the steps taken from now on are recorded.  Moving back to
+    /// an earlier generation (or re-deriving the state after a rule changes) drops the steps
+    /// into the generations that are left behind.
+    ///
+    /// Tracing works out every rule application a second time, so it slows stepping down.
+    pub fn enable_trace(&mut self) {
+        if self.trace.is_none() {
+            self.trace = Some(Vec::new());
+        }
+    }
+
+    /// Stop recording the rules applied by each step, and forget the recorded trace.
+    pub fn disable_trace(&mut self) {
+        self.trace = None;
+    }
+
+    /// Returns the rules applied by each step recorded since [`LSystem::enable_trace()`] was
+    /// called, oldest first, or `None` if tracing isn't enabled.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::{LSystemError, LSystemBuilder};
+    /// use dcc_lsystem::system::FiredRule;
+    ///
+    /// # fn main() -> Result<(), LSystemError> {
+    /// # let mut builder = LSystemBuilder::new();
+    /// # let a = builder.token("a")?;
+    /// # let b = builder.token("b")?;
+    /// # builder.axiom(vec![a])?;
+    /// # builder.transformation_rule(a, vec![a, b])?;
+    /// # builder.transformation_rule(b, vec![a])?;
+    /// # let mut system = builder.finish()?;
+    /// // `system` is an LSystem with axiom `a` and transformation rules `a -> ab`, `b -> a`.
+    /// system.enable_trace();
+    /// system.step_by(2);
+    ///
+    /// // a => ab => aba
+    /// let trace = system.trace().unwrap();
+    /// assert_eq!(trace.len(), 2);
+    /// assert_eq!(trace[1].generation, 2);
+    /// assert_eq!(trace[1].applications[1].position, 1);
+    /// assert_eq!(trace[1].applications[1].predecessor, b);
+    /// assert_eq!(trace[1].applications[1].successor, vec![a]);
+    /// assert_eq!(trace[1].counts()[&(a, FiredRule::Rule(0))], 1);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn trace(&self) -> Option<&[StepTrace]> {
+        self.trace.as_deref()
+    }
+
+    /// Move the system to the given generation.
+    ///
+    /// If caching is enabled (see [`LSystem::enable_cache()`]), the closest cached generation
+    /// at or before `generation` is used as the starting point; otherwise the system only reuses
+    /// its current state when moving forwards, and starts again from the axiom when moving
+    /// backwards.
+    ///
+    /// # Panics
+    /// Panics if there isn't enough memory for one of the generations on the way.  Use
+    /// [`LSystem::try_seek()`] to handle this case.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::{LSystemError, LSystemBuilder};
+    /// # fn main() -> Result<(), LSystemError> {
+    /// # let mut builder = LSystemBuilder::new();
+    /// # let a = builder.token("a")?;
+    /// # let b = builder.token("b")?;
+    /// # builder.axiom(vec![a])?;
+    /// # builder.transformation_rule(a, vec![a, b])?;
+    /// # builder.transformation_rule(b, vec![a])?;
+    /// # let mut system = builder.finish()?;
+    /// // `system` is an LSystem with axiom `a` and transformation rules `a -> ab`, `b -> a`.
+    /// system.enable_cache();
+    ///
+    /// system.seek(5);
+    /// assert_eq!(system.render(), "abaababaabaab");
+    ///
+    /// // Going back to an earlier generation is just a cache lookup
+    /// system.seek(2);
+    /// assert_eq!(system.steps(), 2);
+    /// assert_eq!(system.render(), "aba");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn seek(&mut self, generation: usize) {
+        if let Err(e) = self.try_seek(generation) {
+            panic!("{}", e);
+        }
+    }
+
+    /// Move the system to the given generation, stopping with an
+    /// [`LSystemError::AllocationFailed`] error if there isn't enough memory for one of the
+    /// generations on the way.  The system is left at the last generation that could be
+    /// allocated.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::{LSystemError, LSystemBuilder};
+    /// # fn main() -> Result<(), LSystemError> {
+    /// # let mut builder = LSystemBuilder::new();
+    /// # let a = builder.token("a")?;
+    /// # builder.axiom(vec![a])?;
+    /// # builder.transformation_rule(a, vec![a, a])?;
+    /// # let mut system = builder.finish()?;
+    /// // `system` is an LSystem with axiom `a` and transformation rule `a -> aa`.
+    /// system.try_seek(4)?;
+    /// assert_eq!(system.get_state().len(), 16);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn try_seek(&mut self, generation: usize) -> Result<(), LSystemError> {
+        let cached = self
+            .cache
+            .as_ref()
+            .and_then(|cache| cache.range(..=generation).next_back())
+            .map(|(&steps, _)| steps);
+
+        match cached {
+            // Only restore from the cache if that gets us at least as close as the current state
+            Some(steps) if steps >= self.steps || generation < self.steps => {
+                // unwrap: we just found `steps` in the cache
+                let state = self.cache.as_ref().unwrap()[&steps].clone();
+                let kept = self.steps.min(steps) + 1;
+
+                self.replace_state(state);
+                self.steps = steps;
+                self.sync_history(kept)?;
+            }
+            None if generation < self.steps => self.rewind()?,
+            _ => {}
+        }
+
+        self.try_step_by(generation - self.steps)
+    }
+
+    /// Move the system back a single generation.  This does nothing if the system is still
+    /// at its axiom.
+    ///
+    /// This is the same as seeking to the previous generation, so it is fastest when caching
+    /// is enabled (see [`LSystem::enable_cache()`]).
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::{LSystemError, LSystemBuilder};
+    /// # fn main() -> Result<(), LSystemError> {
+    /// # let mut builder = LSystemBuilder::new();
+    /// # let a = builder.token("a")?;
+    /// # let b = builder.token("b")?;
+    /// # builder.axiom(vec![a])?;
+    /// # builder.transformation_rule(a, vec![a, b])?;
+    /// # let mut system = builder.finish()?;
+    /// // `system` is an LSystem with axiom `a` and transformation rule `a -> ab`.
+    /// system.enable_cache();
+    /// system.step_by(3);
+    ///
+    /// system.step_back();
+    /// assert_eq!(system.render(), "abb");
+    /// system.step_back();
+    /// system.step_back();
+    /// system.step_back();
+    /// assert_eq!(system.render(), "a");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn step_back(&mut self) {
+        if self.steps > 0 {
+            self.seek(self.steps - 1);
+        }
+    }
+
+    /// Limit the memory used by cached generations to roughly `budget` tokens, or remove the
+    /// limit with `None`.  When the cache grows beyond its budget, the oldest generations are
+    /// discarded first.  The axiom and the current generation are always kept, even if that
+    /// means exceeding the budget.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::{LSystemError, LSystemBuilder};
+    /// # fn main() -> Result<(), LSystemError> {
+    /// # let mut builder = LSystemBuilder::new();
+    /// # let a = builder.token("a")?;
+    /// # builder.axiom(vec![a])?;
+    /// # builder.transformation_rule(a, vec![a, a])?;
+    /// # let mut system = builder.finish()?;
+    /// // `system` is an LSystem with axiom `a` and transformation rule `a -> aa`.
+    /// system.enable_cache();
+    /// system.set_cache_budget(Some(100));
+    /// system.step_by(6);
+    ///
+    /// // Generations 5 and 6 have 32 and 64 tokens, which is all the budget allows
+    /// assert_eq!(system.cached_generations(), vec![0, 5, 6]);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_cache_budget(&mut self, budget: Option<usize>) {
+        self.cache_budget = budget;
+        self.trim_cache();
+    }
+
+    /// Discard the oldest cached generations until the cache fits in its budget.
+    fn trim_cache(&mut self) {
+        let current = self.steps;
+        let (Some(cache), Some(budget)) = (self.cache.as_mut(), self.cache_budget) else {
+            return;
+        };
+
+        let mut used: usize = cache.values().map(Vec::len).sum();
+        let evictable: Vec<usize> = cache
+            .keys()
+            .copied()
+            .filter(|&generation| generation != 0 && generation != current)
+            .collect();
+
+        for generation in evictable {
+            if used <= budget {
+                break;
+            }
+
+            // unwrap: `generation` was just taken from the cache
+            used -= cache.remove(&generation).unwrap().len();
+        }
+    }
+
+    /// Returns a [`Snapshot`] of the current state of the system, which can later be passed to
+    /// [`LSystem::restore()`] to return to it.  Snapshots don't record the production rules, so
+    /// they should only be restored while the rules are unchanged.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::{LSystemError, LSystemBuilder};
+    /// # fn main() -> Result<(), LSystemError> {
+    /// # let mut builder = LSystemBuilder::new();
+    /// # let a = builder.token("a")?;
+    /// # let b = builder.token("b")?;
+    /// # builder.axiom(vec![a])?;
+    /// # builder.transformation_rule(a, vec![a, b])?;
+    /// # let mut system = builder.finish()?;
+    /// // `system` is an LSystem with axiom `a` and transformation rule `a -> ab`.
+    /// system.step_by(2);
+    /// let snapshot = system.snapshot();
+    ///
+    /// system.step_by(10);
+    /// system.restore(&snapshot);
+    /// assert_eq!(system.steps(), 2);
+    /// assert_eq!(system.render(), "abb");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            axiom: self.axiom.clone(),
+            resets: self.resets,
+            state: self.state.clone(),
+            steps: self.steps,
+        }
+    }
+
+    /// Return the system to the state recorded in `snapshot`.
+    ///
+    /// # Panics
+    /// Panics if the count history is enabled (see [`LSystem::enable_count_history()`]) and
+    /// there isn't enough memory to derive the generations it is missing.  Use
+    /// [`LSystem::try_restore()`] to handle this case.
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        if let Err(e) = self.try_restore(snapshot) {
+            panic!("{}", e);
+        }
+    }
+
+    /// Return the system to the state recorded in `snapshot`, returning an
+    /// [`LSystemError::AllocationFailed`] error if the count history is enabled and there
+    /// isn't enough memory to derive the generations it is missing.  The state is restored
+    /// either way, but the count history is left incomplete until the next step.
+    pub fn try_restore(&mut self, snapshot: &Snapshot) -> Result<(), LSystemError> {
+        let axiom_changed = self.axiom != snapshot.axiom;
+        let kept = if axiom_changed {
+            0
+        } else {
+            self.steps.min(snapshot.steps) + 1
+        };
+
+        self.axiom.clone_from(&snapshot.axiom);
+        self.resets = snapshot.resets;
+
+        self.replace_state(snapshot.state.clone());
+        self.steps = snapshot.steps;
+
+        if let Some(cache) = self.cache.as_mut() {
+            if axiom_changed {
+                // The cached generations were grown from a different axiom
+                self.enable_cache();
+            } else {
+                cache.insert(self.steps, self.state.clone());
+                self.trim_cache();
+            }
+        }
+
+        self.sync_history(kept)
+    }
+
+    /// Replace the production rule for `predecessor` with `predecessor -> successor`, and bring
+    /// the current state up to date with the new rule.  Only the default rules are changed;
+    /// rule tables (see [`LSystem::set_table()`]) keep their own rules.
+    ///
+    /// Everything before the first generation that contains `predecessor` is unaffected by the
+    /// change.  If caching is enabled (see [`LSystem::enable_cache()`]), cached generations are
+    /// used to find that generation without re-deriving anything, and only the cached
+    /// generations after it are discarded.
+    ///
+    /// When the system is a D0L-system (rewritten in parallel, with a single context-free
+    /// successor for every token) both before and after the change, only the parts of the
+    /// current state that descend from `predecessor` are re-derived.  Every other part of the
+    /// derivation tree grown from that first generation is copied out of the current state,
+    /// and the untouched subtrees below `predecessor` are copied wherever they have been seen
+    /// before.  Otherwise, the rules for one token affect the random choices and contexts of
+    /// every other token, so each generation after the first containing `predecessor` is
+    /// re-derived in full (and cached, if caching is enabled).
+    ///
+    /// Returns an [`LSystemError::InvalidArenaId`] error if any of the provided ids are invalid,
+    /// or an [`LSystemError::AllocationFailed`] error if there isn't enough memory to re-derive
+    /// the state.  In either case the system is left untouched.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::{LSystemError, LSystemBuilder};
+    /// # fn main() -> Result<(), LSystemError> {
+    /// # let mut builder = LSystemBuilder::new();
+    /// # let a = builder.token("a")?;
+    /// # let b = builder.token("b")?;
+    /// # builder.axiom(vec![a])?;
+    /// # builder.transformation_rule(a, vec![a, b])?;
+    /// # let mut system = builder.finish()?;
+    /// // `system` is an LSystem with axiom `a` and transformation rule `a -> ab`.
+    /// system.enable_cache();
+    /// system.step_by(3);
+    /// assert_eq!(system.render(), "abbb");
+    ///
+    /// // `b` first shows up in generation 1, so generations 0 and 1 are kept, and only the
+    /// // parts of generation 3 grown from a `b` are re-derived
+    /// system.replace_rule(b, vec![a])?;
+    /// assert_eq!(system.render(), "abaab");
+    /// assert_eq!(system.cached_generations(), vec![0, 1, 3]);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn replace_rule(
+        &mut self,
+        predecessor: ArenaId,
+        successor: Vec<ArenaId>,
+    ) -> Result<(), LSystemError> {
+        if let Some(&id) = std::iter::once(&predecessor)
+            .chain(successor.iter())
+            .find(|&&id| !self.arena.is_valid(id))
+        {
+            return Err(LSystemError::InvalidArenaId(id));
+        }
+
+        let old_successors = self.successors.clone();
+        self.successors.replace(predecessor, vec![(1.0, successor)]);
+
+        match self.rederive_from_first(predecessor, &old_successors) {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                self.successors = old_successors;
+                Err(e)
+            }
+        }
+    }
+
+    /// Give the system named rule tables, to be used from the given generations onwards.
+    pub(crate) fn set_tables(
+        &mut self,
+        tables: Vec<(String, SuccessorTable)>,
+        schedule: BTreeMap<usize, Option<usize>>,
+    ) {
+        self.tables = tables;
+        self.schedule = schedule;
+    }
+
+    /// Switch the production rules of the system to the rule table called `name` (see
+    /// [`LSystemBuilder::rule_table()`](crate::LSystemBuilder::rule_table())), or back to the
+    /// default rules with `None`.  The switch applies from the current generation onwards;
+    /// earlier generations are still derived with whichever rules were in use at the time.
+    ///
+    /// This function will return an [`LSystemError::UnknownTable`] error if the system has no
+    /// table called `name`.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::LSystemError;
+    /// # fn main() -> Result<(), LSystemError> {
+    /// use dcc_lsystem::builder::TransformationRule;
+    /// use dcc_lsystem::LSystemBuilder;
+    ///
+    /// let mut builder = LSystemBuilder::new();
+    /// let stem = builder.token("S")?;
+    /// let flower = builder.token("*")?;
+    /// builder.axiom(vec![stem])?;
+    ///
+    /// // The stem grows, until it is time to flower
+    /// builder.transformation_rule(stem, vec![stem, stem])?;
+    /// builder.rule_table(
+    ///     "flowering",
+    ///     vec![TransformationRule::new(stem, vec![stem, flower])],
+    /// )?;
+    ///
+    /// let mut system = builder.finish()?;
+    /// system.step_by(2);
+    /// system.set_table(Some("flowering"))?;
+    /// system.step();
+    /// assert_eq!(system.render(), "S*S*S*S*");
+    ///
+    /// // Earlier generations are unaffected by the switch
+    /// system.seek(2);
+    /// assert_eq!(system.render(), "SSSS");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_table(&mut self, name: Option<&str>) -> Result<(), LSystemError> {
+        let index = match name {
+            Some(name) => Some(
+                self.tables
+                    .iter()
+                    .position(|(table, _)| table == name)
+                    .ok_or_else(|| LSystemError::UnknownTable(name.to_string()))?,
+            ),
+            None => None,
+        };
+
+        self.schedule.split_off(&self.steps);
+        self.schedule.insert(self.steps, index);
+
+        // Cached generations after this one may have been derived with other rules
+        let steps = self.steps;
+        if let Some(cache) = self.cache.as_mut() {
+            cache.retain(|&cached, _| cached <= steps);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the name of the rule table used to rewrite the current generation, or `None`
+    /// if the default rules are in use.
+    pub fn table(&self) -> Option<&str> {
+        self.table_index_at(self.steps)
+            .map(|index| self.tables[index].0.as_str())
+    }
+
+    /// Re-derives the current generation, starting from the first generation that contains
+    /// `id`.  The current generation was derived with the rules `old`, and the production
+    /// rules for every token other than `id` must be the same now.
+    fn rederive_from_first(
+        &mut self,
+        id: ArenaId,
+        old: &SuccessorTable,
+    ) -> Result<(), LSystemError> {
+        // Walk forwards from the axiom (through the cache, where we can) until we find
+        // a generation containing `id`.  The generation after each state without `id`
+        // comes out the same under the new rule, so the first generation containing
+        // `id` is the last one we can keep.
+        let mut generation = 0;
+        let mut state = Cow::Borrowed(self.axiom.as_slice());
+
+        while generation < self.steps && !state.contains(&id) {
+            let cached = self
+                .cache
+                .as_ref()
+                .and_then(|cache| cache.get(&(generation + 1)));
+
+            state = match cached {
+                Some(cached) => Cow::Borrowed(cached.as_slice()),
+                None => Cow::Owned(self.rewrite(&state, generation, Vec::new())?.0),
+            };
+
+            generation += 1;
+        }
+
+        if generation == self.steps {
+            // The current state is unaffected, but cached generations after it may not be.
+            if let Some(cache) = self.cache.as_mut() {
+                cache.retain(|&cached, _| cached <= generation);
+            }
+
+            return Ok(());
+        }
+
+        if let Some(new_successors) = self.deterministic_successors() {
+            let old_successors: Option<Vec<&[ArenaId]>> = (0..self.arena.len())
+                .map(|index| old.deterministic(ArenaId(index)))
+                .collect();
+
+            // Both the old and new rules are D0L, so the untouched parts of the current
+            // state can be kept as they are
+            if let Some(old_successors) = old_successors {
+                let depth = self.steps - generation;
+                let splice = Splice::new(
+                    id,
+                    &state,
+                    &self.state,
+                    depth,
+                    old_successors,
+                    new_successors,
+                );
+                let next_state = splice.derive(&state, depth, Vec::new())?;
+
+                if let Some(cache) = self.cache.as_mut() {
+                    cache.retain(|&cached, _| cached <= generation);
+                    cache.insert(self.steps, next_state.clone());
+                }
+
+                self.replace_state(next_state);
+                self.trim_cache();
+                self.sync_history(generation + 1)?;
+
+                return Ok(());
+            }
+        }
+
+        let mut state = state.into_owned();
+        let mut derived = Vec::with_capacity(self.steps - generation);
+
+        for step in generation..self.steps {
+            state = self.rewrite(&state, step, Vec::new())?.0;
+
+            if self.cache.is_some() {
+                derived.push((step + 1, state.clone()));
+            }
+        }
+
+        if let Some(cache) = self.cache.as_mut() {
+            cache.retain(|&cached, _| cached <= generation);
+            cache.extend(derived);
+        }
+
+        self.replace_state(state);
+        self.trim_cache();
+        self.sync_history(generation + 1)
+    }
+
+    /// Runs the rules of this system from each of the given axioms for `steps` steps,
+    /// returning the resulting state and token counts for each axiom.  The system itself
+    /// is left untouched.
+    ///
+    /// Returns an [`LSystemError::EmptyAxiom`] error if any of the axioms are empty, or an
+    /// [`LSystemError::InvalidArenaId`] error if any of the axioms contain a token that doesn't
+    /// belong to this system.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::{LSystemError, LSystemBuilder};
+    /// # fn main() -> Result<(), LSystemError> {
+    /// # let mut builder = LSystemBuilder::new();
+    /// # let a = builder.token("a")?;
+    /// # let b = builder.token("b")?;
+    /// # builder.axiom(vec![a])?;
+    /// # builder.transformation_rule(a, vec![a, b])?;
+    /// # builder.transformation_rule(b, vec![a])?;
+    /// # let system = builder.finish()?;
+    /// // `system` is an LSystem with rules `a -> ab` and `b -> a`.
+    /// let runs = system.compare_axioms(&[vec![a], vec![b], vec![a, b]], 3)?;
+    ///
+    /// assert_eq!(runs[0].state.len(), 5);
+    /// assert_eq!(runs[1].state.len(), 3);
+    /// assert_eq!(runs[2].state.len(), 8);
+    /// assert_eq!(runs[2].counts[&a], 5);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn compare_axioms(
+        &self,
+        axioms: &[Vec<ArenaId>],
+        steps: usize,
+    ) -> Result<Vec<AxiomRun>, LSystemError> {
+        let mut runs = Vec::with_capacity(axioms.len());
+
+        for axiom in axioms {
+            if axiom.is_empty() {
+                return Err(LSystemError::EmptyAxiom);
+            }
+
+            if let Some(&id) = axiom.iter().find(|&&id| !self.arena.is_valid(id)) {
+                return Err(LSystemError::InvalidArenaId(id));
+            }
+
+            let state = self.derive(axiom, steps);
+
+            runs.push(AxiomRun {
+                axiom: axiom.clone(),
+                counts: count_tokens(&state),
+                state,
+            });
+        }
+
+        Ok(runs)
+    }
+
+    /// Returns the number of times each token occurs in the `n`-th generation of the system,
+    /// computed from the production rules without deriving the generation itself.  Tokens that
+    /// don't occur are left out.
+    ///
+    /// Exponentially growing systems quickly outgrow a `usize`, so the counts are returned as
+    /// [`u128`]s.  Counts that don't fit in a [`u128`] saturate at [`u128::MAX`].
+    ///
+    /// Counts can only be predicted for systems that rewrite in [`RewriteMode::Parallel`] and
+    /// give every token exactly one successor, regardless of context; for any other system this
+    /// returns `None`.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::{LSystemError, LSystemBuilder};
+    /// # fn main() -> Result<(), LSystemError> {
+    /// # let mut builder = LSystemBuilder::new();
+    /// # let a = builder.token("a")?;
+    /// # let b = builder.token("b")?;
+    /// # builder.axiom(vec![a])?;
+    /// # builder.transformation_rule(a, vec![a, b])?;
+    /// # builder.transformation_rule(b, vec![a])?;
+    /// # let system = builder.finish()?;
+    /// // `system` is an LSystem with axiom `a` and transformation rules `a -> ab`, `b -> a`.
+    /// let counts = system.predicted_counts(10).unwrap();
+    /// assert_eq!(counts[&a], 89);
+    /// assert_eq!(counts[&b], 55);
+    ///
+    /// // Far beyond anything that could actually be derived
+    /// let counts = system.predicted_counts(150).unwrap();
+    /// assert_eq!(counts[&a], 16130531424904581415797907386349);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn predicted_counts(&self, n: usize) -> Option<HashMap<ArenaId, u128>> {
+        let successors = self.deterministic_successors()?;
+
+        let mut counts = vec![0u128; self.arena.len()];
+        for id in self.axiom.iter() {
+            counts[id.0] += 1;
+        }
+
+        let mut next_counts = vec![0u128; counts.len()];
+
+        for _ in 0..n {
+            next_counts.iter_mut().for_each(|count| *count = 0);
+
+            for (successor, &count) in successors.iter().zip(counts.iter()) {
+                if count == 0 {
+                    continue;
+                }
+
+                for id in successor.iter() {
+                    next_counts[id.0] = next_counts[id.0].saturating_add(count);
+                }
+            }
+
+            if next_counts == counts {
+                // Every later generation has the same counts
+                break;
+            }
+
+            std::mem::swap(&mut counts, &mut next_counts);
+        }
+
+        Some(
+            counts
+                .into_iter()
+                .enumerate()
+                .filter(|&(_, count)| count > 0)
+                .map(|(index, count)| (ArenaId(index), count))
+                .collect(),
+        )
+    }
+
+    /// Returns the length of the `n`-th generation of the system, computed from the production
+    /// rules without deriving the generation itself.  Lengths that don't fit in a [`u128`]
+    /// saturate at [`u128::MAX`].
+    ///
+    /// As with [`LSystem::predicted_counts()`], this returns `None` unless the system rewrites
+    /// in [`RewriteMode::Parallel`] and gives every token exactly one successor, regardless of
+    /// context.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use dcc_lsystem::{LSystemError, LSystemBuilder};
+    /// # fn main() -> Result<(), LSystemError> {
+    /// # let mut builder = LSystemBuilder::new();
+    /// # let a = builder.token("a")?;
+    /// # builder.axiom(vec![a])?;
+    /// # builder.transformation_rule(a, vec![a, a])?;
+    /// # let system = builder.finish()?;
+    /// // `system` is an LSystem with axiom `a` and transformation rule `a -> aa`.
+    /// assert_eq!(system.predicted_len(20), Some(1 << 20));
+    /// assert_eq!(system.predicted_len(127), Some(1 << 127));
+    ///
+    /// // 2^128 doesn't fit in a u128
+    /// assert_eq!(system.predicted_len(128), Some(u128::MAX));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn predicted_len(&self, n: usize) -> Option<u128> {
+        let counts = self.predicted_counts(n)?;
+
+        Some(
+            counts
+                .values()
+                .fold(0u128, |len, &count| len.saturating_add(count)),
+        )
+    }
+}
+
+// The rewriting engine
+impl LSystem {
+    /// Returns the random number generator used to choose between the successors of the
+    /// token at `position` of generation `generation`.
+    ///
+    /// Deriving a fresh stream for every position (rather than drawing from one stream per
+    /// generation) means the choice made for a token doesn't depend on the order in which
+    /// tokens are visited, so chunked, threaded and rope-based expansion all agree.
+    fn choice_rng(&self, generation: usize, position: usize) -> Rng {
+        let generation_seed = Rng::derive(self.seed, generation as u64).next_u64();
+        Rng::derive(generation_seed, position as u64)
+    }
+
+    /// Returns the successor of the token `id` found at `position` of generation `generation`,
+    /// where `at` returns the token at a given position of that generation.
+    #[inline]
+    fn successor<A>(&self, at: &A, id: ArenaId, generation: usize, position: usize) -> &[ArenaId]
+    where
+        A: Fn(usize) -> Option<ArenaId>,
+    {
+        let table = self.rules_at(generation);
+
+        table
+            .contextual(id, position, at)
+            .unwrap_or_else(|| table.choose(id, || self.choice_rng(generation, position).gen_f64()))
+    }
+
+    /// Returns `true` if rewriting the token `id` found at `position` of generation
+    /// `generation` can change it.
+    fn is_rewritable<A>(&self, at: &A, id: ArenaId, generation: usize, position: usize) -> bool
+    where
+        A: Fn(usize) -> Option<ArenaId>,
+    {
+        let table = self.rules_at(generation);
+
+        match table.contextual(id, position, at) {
+            Some(successor) => successor != [id],
+            None => table.is_rewritable(id),
+        }
+    }
+
+    /// Returns the rule table used to rewrite generation `generation`.
+    #[inline]
+    fn rules_at(&self, generation: usize) -> &SuccessorTable {
+        match self.table_index_at(generation) {
+            Some(index) => &self.tables[index].1,
+            None => &self.successors,
+        }
+    }
+
+    /// Returns the index in `tables` of the rule table used to rewrite generation
+    /// `generation`, or `None` for the default rules.
+    #[inline]
+    fn table_index_at(&self, generation: usize) -> Option<usize> {
+        match self.schedule.range(..=generation).next_back() {
+            Some((_, &index)) => index,
+            None => None,
+        }
+    }
+
+    /// Returns the rules applied when rewriting `state`, which is taken to be generation
+    /// `generation` of the system.
+    fn trace_step(&self, state: &[ArenaId], generation: usize) -> StepTrace {
+        let at = |position: usize| state.get(position).copied();
+        let table = self.rules_at(generation);
+
+        let applications = self
+            .successors_of(state, generation)
+            .enumerate()
+            .filter_map(|(position, successor)| {
+                let id = state[position];
+                let successor = successor.filter(|&successor| successor != [id])?;
+
+                let rule = match table.contextual_index(id, position, &at) {
+                    Some(index) => FiredRule::Context(index),
+                    None => FiredRule::Rule(
+                        table.choose_index(id, || self.choice_rng(generation, position).gen_f64()),
+                    ),
+                };
+
+                Some(RuleApplication {
+                    position,
+                    predecessor: id,
+                    rule,
+                    successor: successor.to_vec(),
+                })
+            })
+            .collect();
+
+        StepTrace {
+            generation: generation + 1,
+            table: self
+                .table_index_at(generation)
+                .map(|index| self.tables[index].0.clone()),
+            applications,
+        }
+    }
+
+    /// Applies the production rules of this system once to `state`, which is taken to be
+    /// generation `generation` of the system.  Returns the new state together with the
+    /// number of tokens that were rewritten by a non-trivial rule.
+    ///
+    /// The new state is written into `buffer`, which must be empty.  Returns an
+    /// [`LSystemError::AllocationFailed`] error if the new state can't be allocated.
+    fn rewrite(
+        &self,
+        state: &[ArenaId],
+        generation: usize,
+        buffer: Vec<ArenaId>,
+    ) -> Result<(Vec<ArenaId>, usize), LSystemError> {
+        let (next_state, rules_applied, _) =
+            self.rewrite_fingerprinted(state, generation, buffer, false)?;
+        Ok((next_state, rules_applied))
+    }
+
+    /// Like [`LSystem::rewrite()`], but if `fingerprinted` is set, the fingerprint of the new
+    /// state is also worked out as it is written.  This is only done when rewriting in
+    /// parallel; the sequential modes leave the fingerprint to be worked out when it is asked
+    /// for.
+    fn rewrite_fingerprinted(
+        &self,
+        state: &[ArenaId],
+        generation: usize,
+        buffer: Vec<ArenaId>,
+        fingerprinted: bool,
+    ) -> Result<(Vec<ArenaId>, usize, Option<u64>), LSystemError> {
+        if self.mode == RewriteMode::Parallel {
+            let threads = self.threads.min(state.len() / MIN_CHUNK_LEN).max(1);
+            let fingerprint = fingerprinted.then(Fingerprint::default);
+
+            let (next_state, rules_applied, fingerprint) = if threads == 1 {
+                self.expand_chunk(state, 0..state.len(), generation, buffer, fingerprint)?
+            } else {
+                self.expand_threaded(state, generation, threads, buffer, fingerprint)?
+            };
+
+            return Ok((
+                next_state,
+                rules_applied,
+                fingerprint.map(Fingerprint::finish),
+            ));
+        }
+
+        let at = |position: usize| state.get(position).copied();
+
+        match self.sequential_target(state.len(), &at, generation) {
+            Some(position) => Ok((
+                self.rewrite_at(state, generation, position, buffer)?,
+                1,
+                None,
+            )),
+            None => Ok((extend(buffer, state)?, 0, None)),
+        }
+    }
+
+    /// Returns the position of the token to rewrite when using one of the sequential
+    /// rewrite modes, or `None` if no token in the state can be rewritten.  The state has
+    /// `len` tokens, and `at` returns the token at a given position.
+    fn sequential_target<A>(&self, len: usize, at: &A, generation: usize) -> Option<usize>
+    where
+        A: Fn(usize) -> Option<ArenaId>,
+    {
+        let mut candidates = (0..len).filter(|&position| {
+            // unwrap: `position` is less than `len`
+            self.is_rewritable(at, at(position).unwrap(), generation, position)
+        });
+
+        match self.mode {
+            RewriteMode::Parallel => None,
+            RewriteMode::SequentialFirst => candidates.next(),
+            RewriteMode::SequentialRandom => {
+                let count = candidates.clone().count();
+
+                if count == 0 {
+                    None
+                } else {
+                    let mut rng = Rng::derive(self.seed, generation as u64);
+                    candidates.nth(rng.gen_index(count))
+                }
+            }
+        }
+    }
+
+    /// Rewrites the tokens of `state` (which is generation `generation` of the system) in
+    /// the given range.  Returns the expanded tokens together with the number of tokens that
+    /// were rewritten by a non-trivial rule, and `fingerprint` (if any) extended by the
+    /// expanded tokens.
+    fn expand_chunk(
+        &self,
+        state: &[ArenaId],
+        range: Range<usize>,
+        generation: usize,
+        mut next_state: Vec<ArenaId>,
+        mut fingerprint: Option<Fingerprint>,
+    ) -> Result<(Vec<ArenaId>, usize, Option<Fingerprint>), LSystemError> {
+        let at = |position: usize| state.get(position).copied();
+        let mut rules_applied = 0;
+
+        for position in range {
+            let id = state[position];
+            let successor = self.successor(&at, id, generation, position);
+
+            if successor != [id] {
+                rules_applied += 1;
+            }
+
+            // The buffer grows by doubling, unless `try_step` already reserved the exact
+            // length of the next generation
+            next_state.try_reserve(successor.len())?;
+            next_state.extend_from_slice(successor);
+
+            if let Some(fingerprint) = fingerprint.as_mut() {
+                fingerprint.extend(&self.names, successor);
+            }
+        }
+
+        Ok((next_state, rules_applied, fingerprint))
+    }
+
+    /// Splits `state` into `threads` chunks, expands each chunk on its own thread, and
+    /// concatenates the results in order.  The chunks are fingerprinted separately, and their
+    /// fingerprints joined onto `fingerprint` (if any).
+    fn expand_threaded(
+        &self,
+        state: &[ArenaId],
+        generation: usize,
+        threads: usize,
+        mut next_state: Vec<ArenaId>,
+        mut fingerprint: Option<Fingerprint>,
+    ) -> Result<(Vec<ArenaId>, usize, Option<Fingerprint>), LSystemError> {
+        let chunk_len = state.len().div_ceil(threads);
+        let fingerprinted = fingerprint.map(|_| Fingerprint::default());
+
+        let expanded: Vec<(Vec<ArenaId>, usize, Option<Fingerprint>)> =
+            std::thread::scope(|scope| {
+                let handles: Vec<_> = (0..state.len())
+                    .step_by(chunk_len)
+                    .map(|start| {
+                        let range = start..state.len().min(start + chunk_len);
+                        scope.spawn(move || {
+                            self.expand_chunk(state, range, generation, Vec::new(), fingerprinted)
+                        })
+                    })
+                    .collect();
+
+                handles
+                    .into_iter()
+                    .map(|handle| {
+                        handle
+                            .join()
+                            .unwrap_or_else(|e| std::panic::resume_unwind(e))
+                    })
+                    .collect::<Result<_, _>>()
+            })?;
+
+        next_state.try_reserve(expanded.iter().map(|(chunk, _, _)| chunk.len()).sum())?;
+        let mut rules_applied = 0;
+
+        for (chunk, applied, chunk_fingerprint) in expanded {
+            next_state.extend_from_slice(&chunk);
+            rules_applied += applied;
+
+            if let (Some(fingerprint), Some(chunk_fingerprint)) =
+                (fingerprint.as_mut(), chunk_fingerprint)
+            {
+                fingerprint.join(chunk_fingerprint);
+            }
+        }
+
+        Ok((next_state, rules_applied, fingerprint))
+    }
+
+    /// Returns the number of tokens the tokens of `state` (which is generation `generation`
+    /// of the system) are rewritten to.  Saturates at `usize::MAX`, which no allocation can
+    /// satisfy.
+    fn next_len(&self, state: &[ArenaId], generation: usize) -> usize {
+        let at = |position: usize| state.get(position).copied();
+
+        (0..state.len())
+            .map(|position| {
+                self.successor(&at, state[position], generation, position)
+                    .len()
+            })
+            .fold(0, usize::saturating_add)
+    }
+
+    /// For a D0L-system, returns the length of the next generation together with the number
+    /// of times each token occurs in it, worked out from the counts of the current state and
+    /// the production matrix of the system (see
+    /// [`ProductionMatrix`](crate::analysis::ProductionMatrix)) without looking at the state.
+    /// The length saturates at `usize::MAX`.  Returns `None` for any other system.
+    fn predict_next(&self) -> Option<(usize, Vec<usize>)> {
+        let successors = self.deterministic_successors()?;
+        let counts = self
+            .alphabet_counts
+            .get_or_init(|| alphabet_counts(&self.state, successors.len()));
+
+        let mut len = 0usize;
+        let mut next_counts = vec![0usize; successors.len()];
+
+        for (successor, &count) in successors.iter().zip(counts) {
+            if count == 0 {
+                continue;
+            }
+
+            len = len.saturating_add(successor.len().saturating_mul(count));
+
+            for id in successor.iter() {
+                next_counts[id.0] = next_counts[id.0].saturating_add(count);
+            }
+        }
+
+        Some((len, next_counts))
+    }
+
+    /// Returns the successor of each token of `state` (which is generation `generation` of
+    /// the system), or `None` for the tokens that a sequential [`RewriteMode`] leaves alone.
+    pub(crate) fn successors_of<'s>(
+        &'s self,
+        state: &'s [ArenaId],
+        generation: usize,
+    ) -> impl Iterator<Item = Option<&'s [ArenaId]>> + 's {
+        let at = move |position: usize| state.get(position).copied();
+        let target = self.sequential_target(state.len(), &at, generation);
+
+        state.iter().enumerate().map(move |(position, &id)| {
+            let rewritten = self.mode == RewriteMode::Parallel || target == Some(position);
+            rewritten.then(|| self.successor(&at, id, generation, position))
+        })
+    }
+
+    /// Returns the successor of every token (indexed by [`ArenaId`]), or `None` unless this
+    /// system is a D0L-system: rewritten in parallel, with a single context-free successor
+    /// for each token.
+    pub(crate) fn deterministic_successors(&self) -> Option<Vec<&[ArenaId]>> {
+        if self.mode != RewriteMode::Parallel || !self.schedule.is_empty() {
+            return None;
+        }
+
+        (0..self.arena.len())
+            .map(|index| self.successors.deterministic(ArenaId(index)))
+            .collect()
+    }
+
+    /// Returns `true` if the next few steps can be taken at once by
+    /// [`LSystem::expand_memoized()`].
+    fn can_jump(&self) -> bool {
+        self.memoize
+            && self.subscribers.is_empty()
+            && self.cache.is_none()
+            && self.count_history.is_none()
+            && self.trace.is_none()
+            && self.deterministic_successors().is_some()
+    }
+
+    /// Returns the state `n` generations on from the current one of a D0L-system, written
+    /// into `buffer`.  The expansion of each token `n` generations deep is built once, one
+    /// generation at a time from the expansions of the generation before, and the new state
+    /// is the concatenation of the expansions of the tokens of the current state.
+    fn expand_memoized(
+        &self,
+        n: usize,
+        mut buffer: Vec<ArenaId>,
+    ) -> Result<Vec<ArenaId>, LSystemError> {
+        // unwrap: checked by `can_jump`
+        let successors = self.deterministic_successors().unwrap();
+
+        // Only the tokens that can appear from the current state are worth expanding
+        let mut needed = vec![false; successors.len()];
+        let mut stack = self.state.clone();
+
+        while let Some(id) = stack.pop() {
+            if !std::mem::replace(&mut needed[id.0], true) {
+                stack.extend_from_slice(successors[id.0]);
+            }
+        }
+
+        // The expansion of each needed token, so far
+        let mut expansions: Vec<Vec<ArenaId>> = (0..successors.len())
+            .map(|index| {
+                if needed[index] {
+                    vec![ArenaId(index)]
+                } else {
+                    Vec::new()
+                }
+            })
+            .collect();
+
+        let concat = |tokens: &[ArenaId], expansions: &[Vec<ArenaId>], mut into: Vec<ArenaId>| {
+            into.try_reserve(
+                tokens
+                    .iter()
+                    .map(|id| expansions[id.0].len())
+                    .fold(0, usize::saturating_add),
+            )?;
+
+            for id in tokens {
+                into.extend_from_slice(&expansions[id.0]);
+            }
+
+            Ok::<_, LSystemError>(into)
+        };
+
+        for _ in 1..n {
+            expansions = successors
+                .iter()
+                .enumerate()
+                .map(|(index, successor)| {
+                    if needed[index] {
+                        concat(successor, &expansions, Vec::new())
+                    } else {
+                        Ok(Vec::new())
+                    }
+                })
+                .collect::<Result<_, _>>()?;
+        }
+
+        // The last generation is only ever needed for the tokens of the current state
+        buffer.clear();
+        let mut next_state = buffer;
+        next_state.try_reserve(
+            self.state
+                .iter()
+                .map(|id| {
+                    successors[id.0]
+                        .iter()
+                        .map(|id| expansions[id.0].len())
+                        .fold(0, usize::saturating_add)
+                })
+                .fold(0, usize::saturating_add),
+        )?;
+
+        for id in self.state.iter() {
+            next_state = concat(successors[id.0], &expansions, next_state)?;
+        }
+
+        Ok(next_state)
+    }
+
+    /// Returns the number of tokens in the next generation of the system, without building it.
+    fn next_generation_len(&self) -> usize {
+        let state = &self.state;
+
+        if let Some((len, _)) = self.predict_next() {
+            return len;
+        }
+
+        if self.mode == RewriteMode::Parallel {
+            return self.next_len(state, self.steps);
+        }
+
+        let at = |position: usize| state.get(position).copied();
+
+        match self.sequential_target(state.len(), &at, self.steps) {
+            Some(position) => {
+                let successor = self.successor(&at, state[position], self.steps, position);
+                state.len() - 1 + successor.len()
+            }
+            None => state.len(),
+        }
+    }
+
+    /// Writes a copy of `state` with the token at `position` replaced by its successor
+    /// into `next_state`.
+    fn rewrite_at(
+        &self,
+        state: &[ArenaId],
+        generation: usize,
+        position: usize,
+        mut next_state: Vec<ArenaId>,
+    ) -> Result<Vec<ArenaId>, LSystemError> {
+        let at = |position: usize| state.get(position).copied();
+        let successor = self.successor(&at, state[position], generation, position);
+
+        next_state.try_reserve(state.len() - 1 + successor.len())?;
+        next_state.extend_from_slice(&state[..position]);
+        next_state.extend_from_slice(successor);
+        next_state.extend_from_slice(&state[position + 1..]);
+
+        Ok(next_state)
+    }
+
+    /// Applies the production rules of this system once to `rope`, which is taken to be
+    /// generation `generation` of the system.  Makes the same choices as `rewrite`.
+    fn rewrite_rope(&self, rope: &StateRope, generation: usize) -> StateRope {
+        let at = |position: usize| rope.get(position);
+        let target = self.sequential_target(rope.len(), &at, generation);
+
+        if self.mode != RewriteMode::Parallel && target.is_none() {
+            return rope.clone();
+        }
+
+        let mut next = StateRope::new(rope.chunk_len());
+
+        for (position, id) in rope.iter().enumerate() {
+            if target.is_none() || target == Some(position) {
+                next.extend_from_slice(self.successor(&at, id, generation, position));
+            } else {
+                next.push(id);
+            }
+        }
+
+        next
+    }
+
+    /// Returns generation `n` of the system started from `axiom`.
+    ///
+    /// # Panics
+    /// Panics if there isn't enough memory for one of the generations.
+    fn derive(&self, axiom: &[ArenaId], n: usize) -> Vec<ArenaId> {
+        match self.try_derive(axiom, n) {
+            Ok(state) => state,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Returns generation `n` of the system started from `axiom`, or an
+    /// [`LSystemError::AllocationFailed`] error if there isn't enough memory for one of the
+    /// generations.
+    fn try_derive(&self, axiom: &[ArenaId], n: usize) -> Result<Vec<ArenaId>, LSystemError> {
+        let mut state = axiom.to_vec();
+
+        for generation in 0..n {
+            state = self.rewrite(&state, generation, Vec::new())?.0;
+        }
+
+        Ok(state)
+    }
+
+    /// Returns the generation after `state`, which is taken to be generation `generation`.
+    ///
+    /// # Panics
+    /// Panics if there isn't enough memory for the new generation.
+    fn derive_next(&self, state: &[ArenaId], generation: usize) -> Vec<ArenaId> {
+        match self.rewrite(state, generation, Vec::new()) {
+            Ok((next_state, _)) => next_state,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Returns an empty buffer to write the next state into.  The buffer is reused from the
+    /// previous step or taken from the buffer pool where possible, and has room for at least
+    /// the capacity hint (if any, and if that much memory is available).
+    fn take_buffer(&mut self) -> Vec<ArenaId> {
+        let mut buffer = if self.spare.capacity() > 0 {
+            std::mem::take(&mut self.spare)
+        } else {
+            self.pool.as_ref().map(BufferPool::take).unwrap_or_default()
+        };
+
+        if let Some(hint) = self.capacity_hint {
+            // Failing here is fine: the buffer is grown as needed (and allocation failures
+            // reported) while the next state is written
+            let _ = buffer.try_reserve(hint);
+        }
+
+        buffer
+    }
+
+    /// Brings the count history and the trace (if enabled) up to date with the current state,
+    /// keeping the counts and steps of the first `kept` generations.  See
+    /// `sync_count_history` for the errors this returns.
+    fn sync_history(&mut self, kept: usize) -> Result<(), LSystemError> {
+        // The step into a generation is only kept if the generation before it is too
+        if let Some(trace) = self.trace.as_mut() {
+            let steps = self.steps;
+            trace.retain(|step| step.generation < kept && step.generation <= steps);
+        }
+
+        self.sync_count_history(kept)
+    }
+
+    /// Brings the count history (if enabled) up to date with the current state, keeping the
+    /// counts of the first `kept` generations.  Missing generations are taken from the cache
+    /// where possible, and derived otherwise.
+    ///
+    /// Returns an [`LSystemError::AllocationFailed`] error if there isn't enough memory to
+    /// derive one of the missing generations.  The history is then left with the counts worked
+    /// out so far, and the rest are filled in by the next call.
+    fn sync_count_history(&mut self, kept: usize) -> Result<(), LSystemError> {
+        let Some(mut history) = self.count_history.take() else {
+            return Ok(());
+        };
+
+        history.truncate(kept.min(self.steps));
+        let filled = self.fill_count_history(&mut history);
+        self.count_history = Some(history);
+
+        filled
+    }
+
+    /// Appends the counts of the generations from the end of `history` up to the current state
+    /// to `history`.
+    fn fill_count_history(
+        &self,
+        history: &mut Vec<HashMap<ArenaId, usize>>,
+    ) -> Result<(), LSystemError> {
+        let mut previous: Option<Vec<ArenaId>> = None;
+        for generation in history.len()..self.steps {
+            let cached = self.cache.as_ref().and_then(|cache| cache.get(&generation));
+
+            let state = match (cached, previous) {
+                (Some(cached), _) => cached.clone(),
+                (None, Some(previous)) => self.rewrite(&previous, generation - 1, Vec::new())?.0,
+                (None, None) => self.try_derive(&self.axiom, generation)?,
+            };
+
+            history.push(count_tokens(&state));
+            previous = Some(state);
+        }
+
+        history.push(count_tokens(&self.state));
+
+        Ok(())
+    }
+
+    /// Replace the current state.  The old state is kept for reuse if there is a capacity
+    /// hint, and otherwise handed back to the buffer pool (if any).
+    fn replace_state(&mut self, state: Vec<ArenaId>) {
+        let mut old_state = std::mem::replace(&mut self.state, state);
+        self.fingerprint.take();
+        self.alphabet_counts.take();
+
+        if self.capacity_hint.is_some() {
+            old_state.clear();
+            old_state = std::mem::replace(&mut self.spare, old_state);
+        }
+
+        if let Some(pool) = self.pool.as_ref() {
+            pool.give(old_state);
+        }
+    }
+}
+
+impl<'a> Iterator for SymbolsAtDepth<'a> {
+    type Item = ArenaId;
+
+    fn next(&mut self) -> Option<ArenaId> {
+        let (system, depth, stack, positions) = match &mut self.inner {
+            DepthInner::Expanded(tokens) => return tokens.next(),
+            DepthInner::Lazy {
+                system,
+                depth,
+                stack,
+                positions,
+            } => (*system, *depth, stack, positions),
+        };
+
+        loop {
+            let (generation, tokens) = stack.last_mut()?;
+            let generation = *generation;
+
+            let Some((&id, rest)) = tokens.split_first() else {
+                stack.pop();
+                continue;
+            };
+
+            *tokens = rest;
+
+            if generation == depth {
+                return Some(id);
+            }
+
+            let position = positions[generation];
+            positions[generation] += 1;
+
+            // The system has no context-sensitive rules, so there are no neighbours to look up
+            let successor = system.successor(&|_| None, id, generation, position);
+            stack.push((generation + 1, successor));
+        }
+    }
+}
+
+impl Drop for LSystem {
+    fn drop(&mut self) {
+        if let Some(pool) = self.pool.as_ref() {
+            pool.give(std::mem::take(&mut self.state));
+            pool.give(std::mem::take(&mut self.spare));
+        }
     }
 }
@@ -50,3 +50,1086 @@ fn fractal_binary_tree() -> Result<(), LSystemError> {
 
     Ok(())
 }
+
+#[test]
+fn sequential_rewriting() -> Result<(), LSystemError> {
+    use crate::system::RewriteMode;
+
+    let mut builder = LSystemBuilder::new();
+
+    let a = builder.token("A")?;
+    let b = builder.token("B")?;
+
+    builder.axiom(vec![a, b, a])?;
+    builder.transformation_rule(a, vec![a, b])?;
+    builder.rewrite_mode(RewriteMode::SequentialFirst);
+
+    let mut system = builder.clone().finish()?;
+
+    // Only the leftmost `A` is ever rewritten.
+    system.step();
+    assert_eq!(system.render(), "ABBA");
+    system.step();
+    assert_eq!(system.render(), "ABBBA");
+
+    // In random mode exactly one `A` is rewritten at each step.
+    builder.rewrite_mode(RewriteMode::SequentialRandom);
+    let mut system = builder.finish()?;
+
+    for i in 1..=10 {
+        system.step();
+        assert_eq!(system.render().len(), 3 + i);
+    }
+
+    // Resetting the system replays the same random choices.
+    let rendered = system.render();
+    system.reset();
+    system.step_by(10);
+    assert_eq!(system.render(), rendered);
+
+    Ok(())
+}
+
+#[test]
+fn per_generation_randomness() -> Result<(), LSystemError> {
+    use crate::system::RewriteMode;
+
+    let mut builder = LSystemBuilder::new();
+
+    let a = builder.token("A")?;
+    let b = builder.token("B")?;
+
+    builder.axiom(vec![a, a, a, a, a, a])?;
+    builder.transformation_rule(a, vec![b])?;
+    builder.rewrite_mode(RewriteMode::SequentialRandom);
+    builder.seed(2021);
+
+    let mut system = builder.finish()?;
+    system.step_by(3);
+    let third = system.render();
+
+    // A copy of the system that was reseeded in the meantime still produces the
+    // same generation once the original seed is restored.
+    let mut other = system.clone();
+    other.reset();
+    other.set_seed(1);
+    other.step_by(2);
+    other.reset();
+    other.set_seed(2021);
+    other.step_by(3);
+
+    assert_eq!(other.render(), third);
+
+    Ok(())
+}
+
+#[test]
+fn seek_with_and_without_cache() -> Result<(), LSystemError> {
+    let mut builder = LSystemBuilder::new();
+
+    let a = builder.token("A")?;
+    let b = builder.token("B")?;
+
+    builder.axiom(vec![a])?;
+    builder.transformation_rule(a, vec![a, b])?;
+    builder.transformation_rule(b, vec![a])?;
+
+    let mut uncached = builder.finish()?;
+    let mut cached = uncached.clone();
+    cached.enable_cache();
+
+    for &generation in &[4, 7, 2, 2, 9, 0, 5] {
+        uncached.seek(generation);
+        cached.seek(generation);
+
+        assert_eq!(uncached.steps(), generation);
+        assert_eq!(cached.steps(), generation);
+        assert_eq!(
+            uncached.get_state(),
+            uncached.expanded(generation).as_slice()
+        );
+        assert_eq!(cached.get_state(), uncached.get_state());
+    }
+
+    assert_eq!(cached.cached_generations(), (0..=9).collect::<Vec<_>>());
+
+    Ok(())
+}
+
+#[test]
+fn buffer_pool_reuse() -> Result<(), LSystemError> {
+    use crate::pool::BufferPool;
+
+    let pool = BufferPool::new();
+
+    let mut builder = LSystemBuilder::new();
+    let a = builder.token("A")?;
+    let b = builder.token("B")?;
+    builder.axiom(vec![a])?;
+    builder.transformation_rule(a, vec![a, b])?;
+    builder.transformation_rule(b, vec![a])?;
+
+    let mut plain = builder.clone().finish()?;
+    builder.buffer_pool(pool.clone());
+    let mut pooled = builder.finish()?;
+
+    plain.step_by(10);
+    pooled.step_by(10);
+    assert_eq!(plain.get_state(), pooled.get_state());
+
+    // Stepping hands the previous state back to the pool, and dropping the
+    // system returns its current state as well.
+    assert_eq!(pool.len(), 1);
+    drop(pooled);
+    assert_eq!(pool.len(), 2);
+
+    Ok(())
+}
+
+#[test]
+fn rope_expansion_matches_vec_expansion() -> Result<(), LSystemError> {
+    use crate::system::RewriteMode;
+
+    let mut builder = LSystemBuilder::new();
+    let a = builder.token("A")?;
+    let b = builder.token("B")?;
+    builder.axiom(vec![a, b, a])?;
+    builder.transformation_rule(a, vec![a, b])?;
+    builder.transformation_rule(b, vec![a])?;
+    builder.seed(17);
+
+    for &mode in &[
+        RewriteMode::Parallel,
+        RewriteMode::SequentialFirst,
+        RewriteMode::SequentialRandom,
+    ] {
+        builder.rewrite_mode(mode);
+        let system = builder.clone().finish()?;
+
+        for n in 0..12 {
+            assert_eq!(system.expanded_rope(n, 7).to_vec(), system.expanded(n));
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn accumulated_rules_are_deterministic() -> Result<(), LSystemError> {
+    use crate::builder::DuplicateRulePolicy;
+
+    let mut builder = LSystemBuilder::new();
+    let a = builder.token("A")?;
+    let b = builder.token("B")?;
+    builder.axiom(vec![a])?;
+    builder.duplicate_rule_policy(DuplicateRulePolicy::Accumulate);
+    builder.transformation_rule(a, vec![a, b])?;
+    builder.transformation_rule(a, vec![b, a, a])?;
+    builder.transformation_rule(b, vec![a])?;
+    builder.seed(3);
+
+    let mut system = builder.clone().finish()?;
+    system.step_by(16);
+
+    // Both alternatives for `A` get used
+    let expected = system.get_state().to_vec();
+    assert!(expected.len() > 1 << 15);
+    assert!(system.render().contains("AB") && system.render().contains("BAA"));
+
+    // ...and the choices don't depend on how the expansion is carried out
+    builder.threads(4);
+    let threaded = builder.finish()?;
+    assert_eq!(threaded.expanded(16), expected);
+    assert_eq!(threaded.expanded_rope(16, 5).to_vec(), expected);
+
+    Ok(())
+}
+
+#[test]
+fn predicted_counts_match_derivation() -> Result<(), LSystemError> {
+    use crate::builder::DuplicateRulePolicy;
+
+    let mut builder = LSystemBuilder::new();
+    let a = builder.token("A")?;
+    let b = builder.token("B")?;
+    let c = builder.token("C")?;
+    builder.axiom(vec![c, a])?;
+    builder.transformation_rule(a, vec![a, b, c])?;
+    builder.transformation_rule(b, vec![b, a])?;
+
+    let system = builder.clone().finish()?;
+
+    for n in 0..10 {
+        let state = system.expanded(n);
+        let counts = system.predicted_counts(n).unwrap();
+
+        for id in [a, b, c] {
+            let expected = state.iter().filter(|&&x| x == id).count() as u128;
+            assert_eq!(counts.get(&id).copied().unwrap_or(0), expected);
+        }
+
+        assert_eq!(system.predicted_len(n), Some(state.len() as u128));
+    }
+
+    // Stochastic systems can't be predicted
+    builder.duplicate_rule_policy(DuplicateRulePolicy::Accumulate);
+    builder.transformation_rule(b, vec![b])?;
+    assert_eq!(builder.finish()?.predicted_len(3), None);
+
+    Ok(())
+}
+
+#[test]
+fn stochastic_axiom_resets() -> Result<(), LSystemError> {
+    let mut builder = LSystemBuilder::new();
+    let a = builder.token("A")?;
+    let b = builder.token("B")?;
+    builder.transformation_rule(a, vec![a, b])?;
+    builder.stochastic_axiom(vec![(1.0, vec![a]), (1.0, vec![b]), (1.0, vec![a, b])])?;
+    builder.seed(11);
+
+    let mut first = builder.clone().finish()?;
+    let mut second = builder.finish()?;
+    let mut axioms = Vec::new();
+
+    for _ in 0..20 {
+        assert_eq!(first.get_axiom(), second.get_axiom());
+        axioms.push(first.get_axiom().to_vec());
+
+        // Seeking backwards starts again from the same axiom
+        first.step_by(4);
+        let axiom = first.get_axiom().to_vec();
+        first.seek(1);
+        assert_eq!(first.get_axiom(), axiom.as_slice());
+        assert_eq!(first.get_state(), first.expanded(1).as_slice());
+
+        first.reset();
+        second.reset();
+    }
+
+    assert!(axioms.contains(&vec![a]));
+    assert!(axioms.contains(&vec![b]));
+    assert!(axioms.contains(&vec![a, b]));
+
+    Ok(())
+}
+
+#[test]
+fn failed_resets_leave_the_system_alone() -> Result<(), LSystemError> {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let mut builder = LSystemBuilder::new();
+    let a = builder.token("A")?;
+    builder.transformation_rule(a, vec![a, a])?;
+
+    // The second axiom drawn (the one for the first reset) is empty
+    let draws = AtomicUsize::new(0);
+    builder.axiom_generator(move |_| match draws.fetch_add(1, Ordering::Relaxed) {
+        0 => vec![a],
+        1 => Vec::new(),
+        draw => vec![a; draw],
+    });
+
+    let mut system = builder.finish()?;
+    system.step_by(2);
+
+    assert!(matches!(system.try_reset(), Err(LSystemError::EmptyAxiom)));
+    assert_eq!(system.steps(), 2);
+    assert_eq!(system.render(), "AAAA");
+
+    // Trying again draws a new axiom
+    system.try_reset()?;
+    assert_eq!(system.steps(), 0);
+    assert_eq!(system.render(), "AA");
+
+    Ok(())
+}
+
+#[test]
+fn replace_rule_matches_fresh_derivation() -> Result<(), LSystemError> {
+    use crate::system::RewriteMode;
+
+    for &mode in &[
+        RewriteMode::Parallel,
+        RewriteMode::SequentialFirst,
+        RewriteMode::SequentialRandom,
+    ] {
+        let mut builder = LSystemBuilder::new();
+        let a = builder.token("A")?;
+        let b = builder.token("B")?;
+        let c = builder.token("C")?;
+        builder.axiom(vec![a])?;
+        builder.transformation_rule(a, vec![a, b])?;
+        builder.transformation_rule(b, vec![c])?;
+        builder.rewrite_mode(mode);
+        builder.seed(5);
+
+        let mut cached = builder.clone().finish()?;
+        cached.enable_cache();
+        cached.step_by(8);
+        cached.seek(6);
+
+        let mut uncached = builder.clone().finish()?;
+        uncached.step_by(6);
+
+        for (predecessor, successor) in [(c, vec![a, c]), (b, vec![b, b]), (a, vec![c])] {
+            cached.replace_rule(predecessor, successor.clone())?;
+            uncached.replace_rule(predecessor, successor.clone())?;
+
+            builder.transformation_rule(predecessor, successor)?;
+            let expected = builder.clone().finish()?.expanded(6);
+
+            assert_eq!(cached.get_state(), expected.as_slice());
+            assert_eq!(uncached.get_state(), expected.as_slice());
+
+            // Every generation left in the cache must agree with the new rules
+            for generation in cached.cached_generations() {
+                cached.seek(generation);
+                assert_eq!(cached.get_state(), cached.expanded(generation).as_slice());
+            }
+
+            cached.seek(6);
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn context_rules_across_chunks() -> Result<(), LSystemError> {
+    use crate::system::RewriteMode;
+
+    let mut builder = LSystemBuilder::new();
+    let a = builder.token("A")?;
+    let b = builder.token("B")?;
+
+    // Signals travel right, and split when they hit each other
+    let axiom: Vec<_> = (0..40_000)
+        .map(|i| if i % 7 == 0 { b } else { a })
+        .collect();
+    builder.axiom(axiom)?;
+    builder.context_rule(vec![b], a, vec![], vec![b])?;
+    builder.context_rule(vec![a], b, vec![b], vec![a, b])?;
+    builder.transformation_rule(b, vec![a])?;
+
+    for &mode in &[
+        RewriteMode::Parallel,
+        RewriteMode::SequentialFirst,
+        RewriteMode::SequentialRandom,
+    ] {
+        builder.rewrite_mode(mode);
+
+        let mut system = builder.clone().finish()?;
+        system.step_by(4);
+
+        let mut threaded = builder.clone();
+        threaded.threads(4);
+        let threaded = threaded.finish()?;
+
+        assert_eq!(threaded.expanded(4).as_slice(), system.get_state());
+        assert_eq!(system.expanded_rope(4, 1000).to_vec(), system.get_state());
+    }
+
+    // The contexts are matched against the previous generation
+    let mut builder = LSystemBuilder::new();
+    let a = builder.token("A")?;
+    let b = builder.token("B")?;
+    builder.axiom(vec![b, a, a])?;
+    builder.context_rule(vec![b], a, vec![], vec![b])?;
+
+    let mut system = builder.finish()?;
+    system.step();
+    assert_eq!(system.render(), "BBA");
+    assert_eq!(system.predicted_len(1), None);
+
+    Ok(())
+}
+
+#[test]
+fn symbols_at_depth_matches_expansion() -> Result<(), LSystemError> {
+    use crate::system::RewriteMode;
+
+    let mut builder = LSystemBuilder::new();
+    let a = builder.token("A")?;
+    let b = builder.token("B")?;
+    let c = builder.token("C")?;
+    builder.axiom(vec![a, c, b])?;
+    builder.stochastic_rule(a, vec![(1.0, vec![a, b]), (1.0, vec![b, c, a])])?;
+    builder.transformation_rule(b, vec![a])?;
+    builder.transformation_rule(c, vec![])?;
+    builder.seed(8);
+
+    let system = builder.clone().finish()?;
+    for n in 0..12 {
+        assert_eq!(
+            system.symbols_at_depth(n).collect::<Vec<_>>(),
+            system.expanded(n)
+        );
+    }
+
+    // Sequential systems are derived in full, but still agree
+    builder.rewrite_mode(RewriteMode::SequentialRandom);
+    let system = builder.finish()?;
+    assert_eq!(
+        system.symbols_at_depth(9).collect::<Vec<_>>(),
+        system.expanded(9)
+    );
+
+    Ok(())
+}
+
+#[test]
+fn snapshots_survive_stochastic_resets() -> Result<(), LSystemError> {
+    let mut builder = LSystemBuilder::new();
+    let a = builder.token("A")?;
+    let b = builder.token("B")?;
+    builder.stochastic_axiom(vec![(1.0, vec![a]), (1.0, vec![b, b, b])])?;
+    builder.stochastic_rule(a, vec![(1.0, vec![a, b]), (1.0, vec![b, a])])?;
+    builder.transformation_rule(b, vec![b, a])?;
+    builder.seed(5);
+
+    let mut system = builder.finish()?;
+    system.enable_cache();
+    system.set_cache_budget(Some(64));
+    system.step_by(6);
+    let snapshot = system.snapshot();
+    let expected: Vec<_> = (0..=6).map(|n| system.expanded(n)).collect();
+
+    // Reset until the axiom changes, then jump back to the snapshot
+    while system.get_axiom() == &expected[0][..] {
+        system.reset();
+    }
+    system.restore(&snapshot);
+    assert_eq!(system.get_state(), &expected[6][..]);
+
+    // Stepping back agrees with a fresh derivation from the restored axiom
+    for n in (0..6).rev() {
+        system.step_back();
+        assert_eq!(system.steps(), n);
+        assert_eq!(system.get_state(), &expected[n][..]);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn symbol_limit_matches_next_generation() -> Result<(), LSystemError> {
+    use crate::system::RewriteMode;
+
+    for mode in [RewriteMode::Parallel, RewriteMode::SequentialRandom] {
+        let mut builder = LSystemBuilder::new();
+        let a = builder.token("A")?;
+        let b = builder.token("B")?;
+        builder.axiom(vec![a, b])?;
+        builder.stochastic_rule(a, vec![(1.0, vec![a, b, a]), (2.0, vec![b])])?;
+        builder.transformation_rule(b, vec![a, b])?;
+        builder.rewrite_mode(mode);
+        builder.seed(3);
+
+        let mut system = builder.clone().finish()?;
+        let mut reference = builder.finish()?;
+
+        for _ in 0..10 {
+            reference.step();
+            let len = reference.get_state().len();
+
+            assert!(system.step_by_checked(1, len - 1).is_err());
+            system.step_by_checked(1, len)?;
+            assert_eq!(system.get_state(), reference.get_state());
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn count_history_follows_the_state() -> Result<(), LSystemError> {
+    let mut builder = LSystemBuilder::new();
+    let a = builder.token("A")?;
+    let b = builder.token("B")?;
+    builder.axiom(vec![a, b])?;
+    builder.stochastic_rule(a, vec![(1.0, vec![a, b, a]), (1.0, vec![b])])?;
+    builder.transformation_rule(b, vec![b, a])?;
+    builder.seed(11);
+
+    let check = |system: &LSystem| {
+        let history = system.count_history().unwrap();
+        assert_eq!(history.len(), system.steps() + 1);
+
+        for (generation, counts) in history.iter().enumerate() {
+            let state = system.expanded(generation);
+            let count = |id| state.iter().filter(|&&other| other == id).count();
+
+            assert_eq!(counts.get(&a).copied().unwrap_or(0), count(a));
+            assert_eq!(counts.get(&b).copied().unwrap_or(0), count(b));
+        }
+    };
+
+    let mut system = builder.finish()?;
+    system.step_by(3);
+    system.enable_count_history()?;
+    check(&system);
+
+    system.enable_cache();
+    system.step_by(4);
+    check(&system);
+
+    system.seek(2);
+    check(&system);
+    system.seek(7);
+    check(&system);
+    system.step_back();
+    check(&system);
+
+    system.replace_rule(b, vec![b])?;
+    check(&system);
+    assert_eq!(
+        system.token_counts(),
+        *system.count_history().unwrap().last().unwrap()
+    );
+
+    system.reset();
+    check(&system);
+
+    Ok(())
+}
+
+#[test]
+fn rule_tables_follow_the_schedule() -> Result<(), LSystemError> {
+    use crate::builder::TransformationRule;
+
+    let mut builder = LSystemBuilder::new();
+    let a = builder.token("A")?;
+    let b = builder.token("B")?;
+    builder.axiom(vec![a])?;
+    builder.transformation_rule(a, vec![a, b])?;
+    builder.rule_table(
+        "wild",
+        vec![
+            TransformationRule::weighted(b, vec![b, a], 1.0),
+            TransformationRule::weighted(b, vec![], 1.0),
+        ],
+    )?;
+    builder.table_schedule(3, Some("wild"))?;
+    builder.seed(21);
+    assert!(matches!(
+        builder.table_schedule(5, Some("tame")),
+        Err(LSystemError::UnknownTable(_))
+    ));
+
+    let mut system = builder.finish()?;
+    assert_eq!(system.predicted_len(4), None);
+
+    system.enable_cache();
+    system.step_by(6);
+    assert_eq!(system.table(), Some("wild"));
+    assert_eq!(system.get_state(), &system.expanded(6)[..]);
+
+    // Switching back to the default rules only affects later generations
+    let before = system.expanded(8);
+    system.set_table(None)?;
+    assert_eq!(system.table(), None);
+    assert_eq!(system.cached_generations().last(), Some(&6));
+
+    system.step_by(2);
+    let after = system.get_state().to_vec();
+    assert_ne!(after, before);
+    assert_eq!(after, system.expanded(8));
+
+    system.seek(4);
+    assert_eq!(system.get_state(), &system.expanded(4)[..]);
+    system.seek(8);
+    assert_eq!(system.get_state(), &after[..]);
+
+    Ok(())
+}
+
+#[test]
+fn ignored_tokens_are_skipped_in_contexts() -> Result<(), LSystemError> {
+    let mut builder = LSystemBuilder::new();
+    let a = builder.token("a")?;
+    let b = builder.token("b")?;
+    let open = builder.token("[")?;
+    let close = builder.token("]")?;
+    builder.axiom(vec![a, open, b, close, b])?;
+
+    // a < b > b => a
+    builder.context_rule(vec![a], b, vec![b], vec![a])?;
+    // A context that mentions an ignored token never matches
+    builder.context_rule(vec![open], b, vec![], vec![open])?;
+
+    // Without an ignore list, only the second rule can match
+    let mut system = builder.clone().finish()?;
+    system.step();
+    assert_eq!(system.render(), "a[[]b");
+
+    let mut ignoring = builder.clone();
+    ignoring.context_ignore(vec![open, close])?;
+    let mut system = ignoring.finish()?;
+    system.step();
+    assert_eq!(system.render(), "a[a]b");
+
+    // Removing a token keeps the ignore list pointing at the right tokens
+    builder.context_ignore(vec![open, close])?;
+    builder.remove_token(open)?;
+    let mut system = builder.finish()?;
+    system.step();
+    assert_eq!(system.render(), "aa]b");
+
+    Ok(())
+}
+
+#[test]
+fn birth_generations_line_up_with_the_state() -> Result<(), LSystemError> {
+    use crate::system::RewriteMode;
+
+    for mode in [RewriteMode::Parallel, RewriteMode::SequentialRandom] {
+        let mut builder = LSystemBuilder::new();
+        let a = builder.token("a")?;
+        let b = builder.token("b")?;
+        let c = builder.token("c")?;
+        builder.axiom(vec![a, c])?;
+        builder.stochastic_rule(a, vec![(1.0, vec![a, b]), (1.0, vec![c, a])])?;
+        builder.transformation_rule(b, vec![])?;
+        builder.rewrite_mode(mode);
+        builder.seed(5);
+
+        let system = builder.finish()?;
+
+        assert_eq!(system.birth_generations(0), vec![0, 0]);
+
+        for n in 1..8 {
+            let state = system.expanded(n);
+            let births = system.birth_generations(n);
+            assert_eq!(births.len(), state.len());
+
+            // `c` is never rewritten, so the original `c` is the only token that stays in
+            // generation 0, and nothing is younger than the generation itself
+            assert_eq!(births.last(), Some(&0));
+            assert!(births[..births.len() - 1]
+                .iter()
+                .all(|&birth| 0 < birth && birth <= n));
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn equivalent_stochastic_systems() -> Result<(), LSystemError> {
+    let build = |names: [&str; 2], seed: u64| -> Result<LSystem, LSystemError> {
+        let mut builder = LSystemBuilder::new();
+        let x = builder.token(names[0])?;
+        let y = builder.token(names[1])?;
+        let (a, b) = if names[0] == "a" { (x, y) } else { (y, x) };
+        builder.axiom(vec![a])?;
+        builder.stochastic_rule(a, vec![(1.0, vec![a, b]), (1.0, vec![b, a, a])])?;
+        builder.seed(seed);
+        builder.finish()
+    };
+
+    let system = build(["a", "b"], 7)?;
+    assert!(system.equivalent_to(&build(["b", "a"], 7)?, 8));
+    assert!(!system.equivalent_to(&build(["a", "b"], 8)?, 8));
+
+    // Tokens are compared by name
+    let mut builder = LSystemBuilder::new();
+    let c = builder.token("c")?;
+    builder.axiom(vec![c])?;
+    assert!(!system.equivalent_to(&builder.finish()?, 0));
+
+    Ok(())
+}
+
+#[test]
+fn fingerprints_follow_the_state() -> Result<(), LSystemError> {
+    let build = |names: [&str; 2]| -> Result<LSystem, LSystemError> {
+        let mut builder = LSystemBuilder::new();
+        let x = builder.token(names[0])?;
+        let y = builder.token(names[1])?;
+        let (a, b) = if names[0] == "a" { (x, y) } else { (y, x) };
+        builder.axiom(vec![a])?;
+        builder.transformation_rule(a, vec![a, b])?;
+        builder.transformation_rule(b, vec![a])?;
+        builder.finish()
+    };
+
+    let mut system = build(["a", "b"])?;
+    let mut other = build(["b", "a"])?;
+    system.enable_cache();
+
+    let fingerprints: Vec<u64> = (0..6)
+        .map(|_| {
+            let fingerprint = system.state_fingerprint();
+            assert_eq!(fingerprint, other.state_fingerprint());
+            system.step();
+            other.step();
+            fingerprint
+        })
+        .collect();
+
+    for (i, fingerprint) in fingerprints.iter().enumerate() {
+        assert!(!fingerprints[..i].contains(fingerprint));
+    }
+
+    system.seek(2);
+    assert_eq!(system.state_fingerprint(), fingerprints[2]);
+    system.step_back();
+    assert_eq!(system.state_fingerprint(), fingerprints[1]);
+
+    // The Thue-Morse sequences grown from `a` and `b` are each other's complement, which
+    // defeats polynomial hashes modulo powers of two
+    let mut builder = LSystemBuilder::new();
+    let a = builder.token("a")?;
+    let b = builder.token("b")?;
+    builder.transformation_rule(a, vec![a, b])?;
+    builder.transformation_rule(b, vec![b, a])?;
+
+    for threads in [1, 4] {
+        builder.threads(threads);
+        builder.axiom(vec![a])?;
+        let mut tracked = builder.clone().finish()?;
+        let mut untracked = tracked.clone();
+        builder.axiom(vec![b])?;
+        let mut complement = builder.clone().finish()?;
+
+        // Fingerprints worked out while stepping (on one thread or several) are the same as
+        // the ones worked out from scratch
+        tracked.state_fingerprint();
+        for _ in 0..17 {
+            tracked.step();
+            untracked.step();
+            complement.step();
+            assert_eq!(
+                tracked.state_fingerprint(),
+                untracked.clone().state_fingerprint()
+            );
+            assert_ne!(
+                tracked.state_fingerprint(),
+                complement.clone().state_fingerprint()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn memoized_steps_match_single_steps() -> Result<(), LSystemError> {
+    use std::sync::{Arc, Mutex};
+
+    let mut builder = LSystemBuilder::new();
+    let f = builder.token("F")?;
+    let g = builder.token("G")?;
+    let plus = builder.token("+")?;
+    let x = builder.token("X")?;
+    builder.axiom(vec![x, plus, f])?;
+    builder.transformation_rule(f, vec![f, g, plus, f])?;
+    builder.transformation_rule(g, vec![g, g])?;
+    builder.transformation_rule(x, vec![])?;
+
+    let mut system = builder.clone().finish()?;
+    builder.memoize(true);
+    let mut memoized = builder.finish()?;
+    assert!(memoized.memoized());
+
+    for n in [0, 1, 2, 5] {
+        system.step_by(n);
+        memoized.step_by(n);
+        assert_eq!(system.get_state(), memoized.get_state());
+        assert_eq!(system.steps(), memoized.steps());
+    }
+
+    // Anything that needs to see each generation gets to
+    let steps = Arc::new(Mutex::new(0));
+    let counter = Arc::clone(&steps);
+    memoized.on_step(move |_, _| *counter.lock().unwrap() += 1);
+    memoized.step_by(3);
+    system.step_by(3);
+    assert_eq!(*steps.lock().unwrap(), 3);
+    assert_eq!(system.get_state(), memoized.get_state());
+
+    Ok(())
+}
+
+#[test]
+fn systems_can_be_shared_between_threads() -> Result<(), LSystemError> {
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    assert_send_sync::<LSystem>();
+    assert_send_sync::<LSystemBuilder>();
+    assert_send_sync::<crate::parametric::ParametricLSystem>();
+    assert_send_sync::<crate::derivation::Derivation>();
+    assert_send_sync::<crate::rope::StateRope>();
+
+    let mut builder = LSystemBuilder::new();
+    let a = builder.token("a")?;
+    let b = builder.token("b")?;
+    builder.axiom(vec![a])?;
+    builder.transformation_rule(a, vec![a, b])?;
+    builder.transformation_rule(b, vec![a])?;
+
+    let mut system = builder.finish()?;
+    system.on_step(|_, _| {});
+    system.step_by(15);
+
+    // One derived system, read from several threads at once
+    let renders: Vec<String> = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..4).map(|_| scope.spawn(|| system.render())).collect();
+
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect()
+    });
+
+    assert!(renders.iter().all(|render| *render == system.render()));
+
+    Ok(())
+}
+
+#[test]
+fn traces_record_every_rule_application() -> Result<(), LSystemError> {
+    use crate::builder::TransformationRule;
+    use crate::system::FiredRule;
+
+    let mut builder = LSystemBuilder::new();
+    let a = builder.token("a")?;
+    let b = builder.token("b")?;
+    let c = builder.token("c")?;
+    builder.axiom(vec![a, b, a])?;
+    builder.stochastic_rule(a, vec![(1.0, vec![a, b]), (1.0, vec![c, a])])?;
+    builder.context_rule(vec![a], b, vec![], vec![c])?;
+    builder.rule_table("swap", vec![TransformationRule::new(c, vec![a])])?;
+    builder.table_schedule(3, Some("swap"))?;
+    builder.memoize(true);
+    builder.seed(5);
+    let mut system = builder.finish()?;
+
+    system.enable_trace();
+    let mut states = vec![system.get_state().to_vec()];
+
+    for _ in 0..4 {
+        system.step();
+        states.push(system.get_state().to_vec());
+    }
+
+    let trace = system.trace().unwrap();
+    assert_eq!(trace.len(), 4);
+
+    // Replaying the recorded applications over each generation gives the next one
+    for (step, window) in trace.iter().zip(states.windows(2)) {
+        let mut replayed = Vec::new();
+        let mut applications = step.applications.iter().peekable();
+
+        for (position, &id) in window[0].iter().enumerate() {
+            match applications.next_if(|application| application.position == position) {
+                Some(application) => {
+                    assert_eq!(application.predecessor, id);
+                    replayed.extend_from_slice(&application.successor);
+                }
+                None => replayed.push(id),
+            }
+        }
+
+        assert_eq!(replayed, window[1]);
+    }
+
+    assert_eq!(trace[0].table, None);
+    assert_eq!(trace[3].table.as_deref(), Some("swap"));
+    assert_eq!(trace[0].counts()[&(b, FiredRule::Context(0))], 1);
+    assert!(trace
+        .iter()
+        .any(|step| step.counts().contains_key(&(a, FiredRule::Rule(1)))));
+    assert!(trace[3]
+        .applications
+        .iter()
+        .any(|application| application.predecessor == c && application.successor == [a]));
+
+    // Moving back drops the steps into the generations left behind
+    system.step_back();
+    assert_eq!(system.trace().unwrap().len(), 3);
+    system.reset();
+    assert!(system.trace().unwrap().is_empty());
+
+    // The same goes for moving back through the cache, or to a snapshot
+    let generations = |system: &LSystem| -> Vec<usize> {
+        system
+            .trace()
+            .unwrap()
+            .iter()
+            .map(|step| step.generation)
+            .collect()
+    };
+
+    system.enable_cache();
+    system.step_by(5);
+    assert_eq!(generations(&system), vec![1, 2, 3, 4, 5]);
+    system.seek(3);
+    assert_eq!(generations(&system), vec![1, 2, 3]);
+
+    let snapshot = system.snapshot();
+    system.step_by(2);
+    system.restore(&snapshot);
+    assert_eq!(generations(&system), vec![1, 2, 3]);
+    system.step();
+    assert_eq!(generations(&system), vec![1, 2, 3, 4]);
+    system.step_back();
+    assert_eq!(generations(&system), vec![1, 2, 3]);
+
+    system.disable_trace();
+    assert!(system.trace().is_none());
+
+    Ok(())
+}
+
+#[test]
+fn rendered_states_parse_back() -> Result<(), LSystemError> {
+    let mut builder = LSystemBuilder::new();
+    let stem = builder.token("Stem")?;
+    let leaf = builder.token("Leaf")?;
+    let bud = builder.token("芽")?;
+    let sprout = builder.token("🌱")?;
+    let accent = builder.token("e\u{301}")?;
+    builder.axiom(vec![bud, stem])?;
+    builder.transformation_rule(bud, vec![stem, sprout, bud])?;
+    builder.transformation_rule(stem, vec![stem, leaf, accent])?;
+    let mut system = builder.finish()?;
+    system.step_by(3);
+
+    for separator in [" ", ",", "|", " :: "] {
+        let rendered = system.render_with_separator(separator);
+        assert_eq!(
+            rendered.matches(separator).count(),
+            system.get_state().len() - 1
+        );
+        assert_eq!(
+            system.parse_state(&rendered, separator)?,
+            system.get_state()
+        );
+    }
+
+    // None of the names is a prefix of another, so even the plain rendering parses back
+    assert_eq!(
+        system.parse_state(&system.render(), "")?,
+        system.get_state()
+    );
+    assert_eq!(system.render_with_separator(""), system.render());
+
+    assert!(system.parse_state("", " ")?.is_empty());
+    assert!(system.parse_state("", "")?.is_empty());
+    assert!(matches!(
+        system.parse_state("Stem  Leaf", " "),
+        Err(LSystemError::UnknownToken(name)) if name.is_empty()
+    ));
+    assert!(matches!(
+        system.parse_state("Stem🌿", ""),
+        Err(LSystemError::UnknownToken(name)) if name == "🌿"
+    ));
+
+    // A token name containing the separator makes the rendering ambiguous
+    let mut builder = LSystemBuilder::new();
+    let a = builder.token("a")?;
+    let pair = builder.token("a,b")?;
+    builder.token("b")?;
+    builder.axiom(vec![a, pair])?;
+    let system = builder.finish()?;
+
+    let rendered = system.render_with_separator(",");
+    assert_eq!(rendered, "a,a,b");
+    assert!(matches!(
+        system.parse_state(&rendered, ","),
+        Err(LSystemError::AmbiguousSeparator { token, separator })
+            if token == "a,b" && separator == ","
+    ));
+    assert_eq!(system.parse_state("a a,b", " ")?, system.get_state());
+
+    // Without a separator, a name that is a prefix of another can be read back wrongly
+    let mut builder = LSystemBuilder::new();
+    let f = builder.token("F")?;
+    let one = builder.token("1")?;
+    let f1 = builder.token("F1")?;
+    builder.axiom(vec![f, one])?;
+    let system = builder.finish()?;
+
+    assert_eq!(system.render(), "F1");
+    assert_eq!(system.parse_state(&system.render(), "")?, vec![f1]);
+    assert_eq!(
+        system.parse_state(&system.render_with_separator(" "), " ")?,
+        vec![f, one]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn predicted_lengths_follow_the_state() -> Result<(), LSystemError> {
+    let mut builder = LSystemBuilder::new();
+    let a = builder.token("a")?;
+    let b = builder.token("b")?;
+    let c = builder.token("c")?;
+    builder.axiom(vec![a, c])?;
+    builder.transformation_rule(a, vec![a, b, c])?;
+    builder.transformation_rule(b, vec![a])?;
+    builder.transformation_rule(c, vec![])?;
+    let mut system = builder.finish()?;
+    system.enable_cache();
+
+    // The limit checks use the predicted length of the next generation, so they can only
+    // agree with the generations actually built if the predictions are exact
+    fn check_next_step(system: &mut LSystem) -> Result<(), LSystemError> {
+        let len = system.expanded(system.steps() + 1).len();
+        assert!(system.step_by_checked(1, len - 1).is_err());
+        system.step_by_checked(1, len)?;
+        assert_eq!(system.get_state().len(), len);
+        Ok(())
+    }
+
+    for _ in 0..6 {
+        check_next_step(&mut system)?;
+    }
+
+    system.seek(2);
+    check_next_step(&mut system)?;
+    system.replace_rule(b, vec![b, b])?;
+    check_next_step(&mut system)?;
+    system.reset();
+    check_next_step(&mut system)?;
+
+    Ok(())
+}
+
+#[test]
+fn huge_weights_still_choose_every_alternative() -> Result<(), LSystemError> {
+    use std::collections::{HashMap, HashSet};
+
+    let mut builder = LSystemBuilder::new();
+    let a = builder.token("a")?;
+    let b = builder.token("b")?;
+    let c = builder.token("c")?;
+
+    // The weights are finite, but their total isn't
+    builder.stochastic_rule(a, vec![(f64::MAX, vec![b]), (f64::MAX, vec![c])])?;
+    builder.stochastic_axiom(vec![(1e308, vec![a; 64]), (1e308, vec![b])])?;
+    builder.seed(9);
+    let mut system = builder.finish()?;
+
+    let mut axioms = HashSet::new();
+    let mut counts = HashMap::new();
+
+    for _ in 0..20 {
+        axioms.insert(system.get_axiom().to_vec());
+        system.step();
+
+        for (id, count) in system.token_counts() {
+            *counts.entry(id).or_insert(0) += count;
+        }
+
+        system.reset();
+    }
+
+    assert_eq!(axioms.len(), 2);
+    assert!(counts[&b] > 0 && counts[&c] > 0);
+
+    Ok(())
+}